@@ -0,0 +1,290 @@
+// Face alignment: a similarity transform (rotation, uniform scale,
+// translation) maps the face keypoints onto a reference template, and the face
+// is resampled through it into a square chip in canonical position, as
+// recognition, attribute and liveness models expect.
+
+use visioncore_plugin::{Face, FaceKeypoint, FrameRef, PixelFormat};
+
+use crate::config::Normalization;
+use crate::error::LocinetError;
+use crate::preprocess::{check_tensor, sample_bilinear};
+
+// x' = a * x - b * y + tx, y' = b * x + a * y + ty
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Similarity {
+    pub a: f32,
+    pub b: f32,
+    pub tx: f32,
+    pub ty: f32,
+}
+
+impl Similarity {
+    // Least-squares similarity taking `src` points onto `dst` (Umeyama,
+    // without reflection). None for degenerate input, e.g. coincident points
+    // on either side.
+    pub fn estimate(src: &[[f32; 2]], dst: &[[f32; 2]]) -> Option<Self> {
+        let n = src.len().min(dst.len());
+        if n < 2 {
+            return None;
+        }
+        let mean = |points: &[[f32; 2]]| {
+            let [x, y] = points[..n].iter().fold([0.0, 0.0], |[x, y], p| [x + p[0], y + p[1]]);
+            [x / n as f32, y / n as f32]
+        };
+        let (src_mean, dst_mean) = (mean(src), mean(dst));
+
+        let (mut dot, mut cross, mut variance) = (0.0, 0.0, 0.0);
+        for (s, d) in src.iter().zip(dst).take(n) {
+            let (sx, sy) = (s[0] - src_mean[0], s[1] - src_mean[1]);
+            let (dx, dy) = (d[0] - dst_mean[0], d[1] - dst_mean[1]);
+            dot += sx * dx + sy * dy;
+            cross += sx * dy - sy * dx;
+            variance += sx * sx + sy * sy;
+        }
+        if variance <= f32::EPSILON {
+            return None;
+        }
+
+        let a = dot / variance;
+        let b = cross / variance;
+        // Coincident `dst` points: everything would map to a single point
+        if a * a + b * b <= f32::EPSILON {
+            return None;
+        }
+        Some(Similarity {
+            a,
+            b,
+            tx: dst_mean[0] - (a * src_mean[0] - b * src_mean[1]),
+            ty: dst_mean[1] - (b * src_mean[0] + a * src_mean[1]),
+        })
+    }
+
+    pub fn apply(self, [x, y]: [f32; 2]) -> [f32; 2] {
+        [self.a * x - self.b * y + self.tx, self.b * x + self.a * y + self.ty]
+    }
+
+    // The reverse mapping; None if the transform collapses everything to a point
+    pub fn inverse(self) -> Option<Self> {
+        let det = self.a * self.a + self.b * self.b;
+        if det <= f32::EPSILON {
+            return None;
+        }
+        let (a, b) = (self.a / det, -self.b / det);
+        Some(Similarity {
+            a,
+            b,
+            tx: -(a * self.tx - b * self.ty),
+            ty: -(b * self.tx + a * self.ty),
+        })
+    }
+
+    // Uniform scale factor
+    pub fn scale(self) -> f32 {
+        self.a.hypot(self.b)
+    }
+
+    // Rotation in radians, clockwise in image coordinates
+    pub fn rotation(self) -> f32 {
+        self.b.atan2(self.a)
+    }
+}
+
+// Where the keypoints of an aligned face should land in a square chip
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlignmentTemplate {
+    // Chip side in pixels
+    pub size: usize,
+    // Target position of each keypoint, in chip pixels
+    pub points: Vec<(FaceKeypoint, [f32; 2])>,
+}
+
+impl AlignmentTemplate {
+    // The ArcFace 112x112 template used by most recognition models. The mouth
+    // center is the mean of ArcFace's two mouth corners; BlazeFace has no
+    // corners, and its ear keypoints are too unstable to align on.
+    pub fn arcface() -> Self {
+        AlignmentTemplate {
+            size: 112,
+            points: vec![
+                (FaceKeypoint::RightEye, [38.2946, 51.6963]),
+                (FaceKeypoint::LeftEye, [73.5318, 51.5014]),
+                (FaceKeypoint::NoseTip, [56.0252, 71.7366]),
+                (FaceKeypoint::Mouth, [56.1396, 92.2848]),
+            ],
+        }
+    }
+
+    // The same template for a chip of `size` pixels
+    pub fn with_size(&self, size: usize) -> Self {
+        let scale = size as f32 / self.size as f32;
+        AlignmentTemplate {
+            size,
+            points: self.points.iter().map(|&(keypoint, [x, y])| (keypoint, [x * scale, y * scale])).collect(),
+        }
+    }
+
+    // Transform from chip pixels to frame pixels that best puts `face`'s
+    // keypoints on the template. None for degenerate keypoints.
+    pub fn chip_to_frame(&self, face: &Face) -> Option<Similarity> {
+        let template: Vec<[f32; 2]> = self.points.iter().map(|&(_, point)| point).collect();
+        let keypoints: Vec<[f32; 2]> = self.points.iter().map(|&(keypoint, _)| face.keypoint(keypoint)).collect();
+        Similarity::estimate(&template, &keypoints)
+    }
+
+    // Warp the aligned face straight into a model input tensor (size * size * 3
+    // floats, HWC), skipping the 8-bit chip. Returns the chip to frame
+    // transform, or None if the keypoints are degenerate.
+    pub fn warp_into(
+        &self,
+        frame: &FrameRef,
+        face: &Face,
+        normalization: Normalization,
+        tensor: &mut [f32],
+    ) -> Result<Option<Similarity>, LocinetError> {
+        check_tensor(tensor, self.size)?;
+        let Some(transform) = self.chip_to_frame(face) else {
+            return Ok(None);
+        };
+        warp(frame, transform, self.size, tensor, |value| normalization.apply_f32(value));
+        Ok(Some(transform))
+    }
+}
+
+impl Default for AlignmentTemplate {
+    fn default() -> Self {
+        Self::arcface()
+    }
+}
+
+// A face cut out of a frame in canonical position, as packed RGB
+#[derive(Debug, Clone)]
+pub struct AlignedChip {
+    pub size: usize,
+    // size * size RGB pixels, row by row
+    pub data: Vec<u8>,
+    // Chip pixels to frame pixels
+    pub transform: Similarity,
+}
+
+impl AlignedChip {
+    // Warp the face from `frame` onto `template` with bilinear sampling.
+    // Samples outside the frame are black. None if the keypoints are
+    // degenerate.
+    pub fn extract(frame: &FrameRef, face: &Face, template: &AlignmentTemplate) -> Option<Self> {
+        let transform = template.chip_to_frame(face)?;
+        let mut data = vec![0; template.size * template.size * 3];
+        warp(frame, transform, template.size, &mut data, |value| value.round().clamp(0.0, 255.0) as u8);
+        Some(AlignedChip { size: template.size, data, transform })
+    }
+
+    // The chip as a frame, to hand to anything that takes one
+    pub fn as_frame_ref(&self) -> FrameRef<'_> {
+        FrameRef::new(&self.data, self.size as u32, self.size as u32, PixelFormat::Rgb, self.size as u32 * 3)
+    }
+
+    // Chip pixels to frame pixels, e.g. for landmarks found on the chip
+    pub fn to_frame(&self, point: [f32; 2]) -> [f32; 2] {
+        self.transform.apply(point)
+    }
+
+    // Frame pixels to chip pixels
+    pub fn from_frame(&self, point: [f32; 2]) -> Option<[f32; 2]> {
+        Some(self.transform.inverse()?.apply(point))
+    }
+
+    // Fill a model input tensor (size * size * 3 floats, HWC) from the chip
+    pub fn write_tensor(&self, normalization: Normalization, tensor: &mut [f32]) -> Result<(), LocinetError> {
+        check_tensor(tensor, self.size)?;
+        for (value, &channel) in tensor.iter_mut().zip(&self.data) {
+            *value = normalization.apply(channel);
+        }
+        Ok(())
+    }
+}
+
+// Sample the frame at the center of each chip pixel and store it with `store`
+fn warp<T>(frame: &FrameRef, transform: Similarity, size: usize, out: &mut [T], store: impl Fn(f32) -> T) {
+    for (v, row) in out.chunks_exact_mut(size * 3).enumerate() {
+        for (u, pixel) in row.chunks_exact_mut(3).enumerate() {
+            let [x, y] = transform.apply([u as f32 + 0.5, v as f32 + 0.5]);
+            let rgb = sample_bilinear(frame, x - 0.5, y - 0.5);
+            for (value, channel) in pixel.iter_mut().zip(rgb) {
+                *value = store(channel);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: [f32; 2], expected: [f32; 2]) {
+        assert!(
+            (actual[0] - expected[0]).abs() < 1e-3 && (actual[1] - expected[1]).abs() < 1e-3,
+            "{:?} is not {:?}", actual, expected
+        );
+    }
+
+    // Scale 1.5, rotation 30 degrees, translation (40, -12)
+    fn known() -> Similarity {
+        let (sin, cos) = 30f32.to_radians().sin_cos();
+        Similarity { a: 1.5 * cos, b: 1.5 * sin, tx: 40.0, ty: -12.0 }
+    }
+
+    #[test]
+    fn estimate_recovers_a_known_transform() {
+        let known = known();
+        let src: Vec<[f32; 2]> = AlignmentTemplate::arcface().points.iter().map(|&(_, point)| point).collect();
+        let dst: Vec<[f32; 2]> = src.iter().map(|&point| known.apply(point)).collect();
+
+        let estimated = Similarity::estimate(&src, &dst).unwrap();
+        assert!((estimated.scale() - 1.5).abs() < 1e-4);
+        assert!((estimated.rotation() - 30f32.to_radians()).abs() < 1e-4);
+        assert!((estimated.tx - known.tx).abs() < 1e-2 && (estimated.ty - known.ty).abs() < 1e-2);
+        for (&s, &d) in src.iter().zip(&dst) {
+            assert_close(estimated.apply(s), d);
+        }
+    }
+
+    #[test]
+    fn inverse_undoes_apply() {
+        let transform = known();
+        let inverse = transform.inverse().unwrap();
+        for point in [[0.0, 0.0], [112.0, 0.0], [56.0, 71.7], [-30.0, 250.0]] {
+            assert_close(inverse.apply(transform.apply(point)), point);
+            assert_close(transform.apply(inverse.apply(point)), point);
+        }
+
+        let collapsed = Similarity { a: 0.0, b: 0.0, tx: 5.0, ty: 5.0 };
+        assert!(collapsed.inverse().is_none());
+    }
+
+    #[test]
+    fn degenerate_points_have_no_transform() {
+        assert!(Similarity::estimate(&[[1.0, 1.0]], &[[2.0, 2.0]]).is_none());
+        assert!(Similarity::estimate(&[[1.0, 1.0], [1.0, 1.0]], &[[0.0, 0.0], [5.0, 5.0]]).is_none());
+        assert!(Similarity::estimate(&[[0.0, 0.0], [5.0, 5.0]], &[[1.0, 1.0], [1.0, 1.0]]).is_none());
+    }
+
+    #[test]
+    fn wrong_tensor_size_is_an_error() {
+        let chip = AlignedChip { size: 4, data: vec![0; 4 * 4 * 3], transform: known() };
+        assert!(chip.write_tensor(Normalization::Unit, &mut [0.0; 4 * 4 * 3]).is_ok());
+        assert!(matches!(
+            chip.write_tensor(Normalization::Unit, &mut [0.0; 4 * 4]),
+            Err(LocinetError::ShapeMismatch { .. })
+        ));
+
+        let data = vec![0; 8 * 8 * 3];
+        let frame = FrameRef::rgb(&data, 8, 8);
+        let face = Face::new([0.0, 0.0, 8.0, 8.0], [0.0; 4], [4.0, 4.0], [[4.0, 4.0]; 6], 0.9, 8, 8);
+        let template = AlignmentTemplate::arcface().with_size(4);
+        assert!(matches!(
+            template.warp_into(&frame, &face, Normalization::Unit, &mut [0.0; 10]),
+            Err(LocinetError::ShapeMismatch { .. })
+        ));
+        // Every keypoint in one place cannot be aligned
+        assert!(matches!(template.warp_into(&frame, &face, Normalization::Unit, &mut [0.0; 4 * 4 * 3]), Ok(None)));
+    }
+}
@@ -0,0 +1,307 @@
+// SSD anchor generation following MediaPipe's SsdAnchorsCalculator, so the
+// anchor tables of MediaPipe's SSD-style models can be reproduced from the
+// options listed in their graph configs.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::LocinetError;
+
+// Anchor box in normalized input coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Anchor {
+    pub x_center: f32,
+    pub y_center: f32,
+    pub w: f32,
+    pub h: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SsdAnchorOptions {
+    pub input_size_width: u32,
+    pub input_size_height: u32,
+    pub num_layers: usize,
+    // Feature map stride of each layer; consecutive layers with the same
+    // stride share one feature map
+    pub strides: Vec<u32>,
+    pub min_scale: f32,
+    pub max_scale: f32,
+    // Anchor center within its feature map cell
+    pub anchor_offset_x: f32,
+    pub anchor_offset_y: f32,
+    pub aspect_ratios: Vec<f32>,
+    // Adds an anchor at the geometric mean of this and the next layer's
+    // scale with this aspect ratio; 0 disables it
+    pub interpolated_scale_aspect_ratio: f32,
+    // Use three fixed boxes (scale 0.1, aspect ratios 1, 2, 0.5) on the first layer
+    pub reduce_boxes_in_lowest_layer: bool,
+    // Every anchor gets w = h = 1, so regressions are read in input units
+    pub fixed_anchor_size: bool,
+}
+
+impl SsdAnchorOptions {
+    // BlazeFace short-range (front camera) model: 128x128 input, 896 anchors
+    pub fn blazeface_short_range() -> Self {
+        SsdAnchorOptions {
+            input_size_width: 128,
+            input_size_height: 128,
+            num_layers: 4,
+            strides: vec![8, 16, 16, 16],
+            min_scale: 0.1484375,
+            max_scale: 0.75,
+            anchor_offset_x: 0.5,
+            anchor_offset_y: 0.5,
+            aspect_ratios: vec![1.0],
+            interpolated_scale_aspect_ratio: 1.0,
+            reduce_boxes_in_lowest_layer: false,
+            fixed_anchor_size: true,
+        }
+    }
+
+    // BlazeFace full-range model: 192x192 input, one 48x48 layer, 2304 anchors
+    pub fn blazeface_full_range() -> Self {
+        SsdAnchorOptions {
+            input_size_width: 192,
+            input_size_height: 192,
+            num_layers: 1,
+            strides: vec![4],
+            interpolated_scale_aspect_ratio: 0.0,
+            ..Self::blazeface_short_range()
+        }
+    }
+
+    // BlazeFace back-camera model: 256x256 input, 896 anchors
+    pub fn blazeface_back() -> Self {
+        SsdAnchorOptions {
+            input_size_width: 256,
+            input_size_height: 256,
+            strides: vec![16, 32, 32, 32],
+            min_scale: 0.15625,
+            ..Self::blazeface_short_range()
+        }
+    }
+
+    // Anchors of the BlazeFace variant with this square input size
+    pub fn for_input_size(input_size: usize) -> Option<Self> {
+        match input_size {
+            128 => Some(Self::blazeface_short_range()),
+            192 => Some(Self::blazeface_full_range()),
+            256 => Some(Self::blazeface_back()),
+            _ => None,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), LocinetError> {
+        let invalid = |message: String| Err(LocinetError::InvalidConfig(format!("anchors: {}", message)));
+        if self.input_size_width == 0 || self.input_size_height == 0 {
+            return invalid(format!("input size must be positive, got {}x{}", self.input_size_width, self.input_size_height));
+        }
+        if self.strides.is_empty() || self.strides.contains(&0) {
+            return invalid(format!("strides must be a non-empty list of positive numbers, got {:?}", self.strides));
+        }
+        if self.num_layers == 0 || self.num_layers > self.strides.len() {
+            return invalid(format!("num_layers must be between 1 and the number of strides ({}), got {}", self.strides.len(), self.num_layers));
+        }
+        let positive = |value: f32| value.is_finite() && value > 0.0;
+        if !(positive(self.min_scale) && positive(self.max_scale)) {
+            return invalid(format!("scales must be positive, got {} and {}", self.min_scale, self.max_scale));
+        }
+        if !self.aspect_ratios.iter().all(|&ratio| positive(ratio)) {
+            return invalid(format!("aspect_ratios must be positive, got {:?}", self.aspect_ratios));
+        }
+        if !(self.interpolated_scale_aspect_ratio.is_finite() && self.interpolated_scale_aspect_ratio >= 0.0) {
+            return invalid(format!("interpolated_scale_aspect_ratio must not be negative, got {}", self.interpolated_scale_aspect_ratio));
+        }
+        Ok(())
+    }
+
+    // Layers anchors are generated for; extra strides are ignored
+    fn layers(&self) -> usize {
+        self.num_layers.min(self.strides.len())
+    }
+
+    fn scale(&self, layer: usize) -> f32 {
+        if self.layers() == 1 {
+            (self.min_scale + self.max_scale) * 0.5
+        } else {
+            self.min_scale + (self.max_scale - self.min_scale) * layer as f32 / (self.layers() - 1) as f32
+        }
+    }
+}
+
+pub fn generate_anchors(options: &SsdAnchorOptions) -> Vec<Anchor> {
+    let mut anchors = Vec::new();
+    let num_layers = options.layers();
+
+    let mut layer = 0;
+    while layer < num_layers {
+        // Box shapes of all layers that share this feature map
+        let mut aspect_ratios = Vec::new();
+        let mut scales = Vec::new();
+        let mut last_same_stride_layer = layer;
+        while last_same_stride_layer < num_layers
+            && options.strides[last_same_stride_layer] == options.strides[layer]
+        {
+            let scale = options.scale(last_same_stride_layer);
+            if last_same_stride_layer == 0 && options.reduce_boxes_in_lowest_layer {
+                aspect_ratios.extend([1.0, 2.0, 0.5]);
+                scales.extend([0.1, scale, scale]);
+            } else {
+                for &aspect_ratio in &options.aspect_ratios {
+                    aspect_ratios.push(aspect_ratio);
+                    scales.push(scale);
+                }
+                if options.interpolated_scale_aspect_ratio > 0.0 {
+                    let scale_next = if last_same_stride_layer == num_layers - 1 {
+                        1.0
+                    } else {
+                        options.scale(last_same_stride_layer + 1)
+                    };
+                    scales.push((scale * scale_next).sqrt());
+                    aspect_ratios.push(options.interpolated_scale_aspect_ratio);
+                }
+            }
+            last_same_stride_layer += 1;
+        }
+
+        let shapes: Vec<(f32, f32)> = aspect_ratios
+            .iter()
+            .zip(&scales)
+            .map(|(&aspect_ratio, &scale)| {
+                let ratio_sqrt = aspect_ratio.sqrt();
+                (scale * ratio_sqrt, scale / ratio_sqrt)
+            })
+            .collect();
+
+        let stride = options.strides[layer];
+        let feature_map_width = options.input_size_width.div_ceil(stride);
+        let feature_map_height = options.input_size_height.div_ceil(stride);
+        for y in 0..feature_map_height {
+            for x in 0..feature_map_width {
+                for &(w, h) in &shapes {
+                    let (w, h) = if options.fixed_anchor_size { (1.0, 1.0) } else { (w, h) };
+                    anchors.push(Anchor {
+                        x_center: (x as f32 + options.anchor_offset_x) / feature_map_width as f32,
+                        y_center: (y as f32 + options.anchor_offset_y) / feature_map_height as f32,
+                        w,
+                        h,
+                    });
+                }
+            }
+        }
+
+        layer = last_same_stride_layer;
+    }
+
+    anchors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_center(anchor: &Anchor, x_center: f32, y_center: f32) {
+        assert!(
+            (anchor.x_center - x_center).abs() < 1e-6 && (anchor.y_center - y_center).abs() < 1e-6,
+            "anchor at ({}, {}), expected ({}, {})", anchor.x_center, anchor.y_center, x_center, y_center
+        );
+    }
+
+    #[test]
+    fn front_camera_has_896_anchors() {
+        // 16x16 cells with 2 anchors, then 8x8 cells with 6 anchors
+        let anchors = generate_anchors(&SsdAnchorOptions::blazeface_short_range());
+        assert_eq!(anchors.len(), 16 * 16 * 2 + 8 * 8 * 6);
+        assert_eq!(anchors.len(), 896);
+        assert!(anchors.iter().all(|anchor| anchor.w == 1.0 && anchor.h == 1.0));
+    }
+
+    #[test]
+    fn back_camera_has_896_anchors() {
+        // Twice the input size with twice the strides gives the same grid
+        let anchors = generate_anchors(&SsdAnchorOptions::blazeface_back());
+        assert_eq!(anchors.len(), 896);
+        assert_eq!(anchors, generate_anchors(&SsdAnchorOptions::blazeface_short_range()));
+    }
+
+    #[test]
+    fn full_range_has_2304_anchors() {
+        assert_eq!(generate_anchors(&SsdAnchorOptions::blazeface_full_range()).len(), 48 * 48);
+    }
+
+    #[test]
+    fn anchor_centers() {
+        let anchors = generate_anchors(&SsdAnchorOptions::blazeface_short_range());
+
+        // Stride 8 layer: two anchors per cell, row by row
+        assert_center(&anchors[0], 0.5 / 16.0, 0.5 / 16.0);
+        assert_center(&anchors[1], 0.5 / 16.0, 0.5 / 16.0);
+        assert_center(&anchors[2], 1.5 / 16.0, 0.5 / 16.0);
+        assert_center(&anchors[32], 0.5 / 16.0, 1.5 / 16.0);
+        assert_center(&anchors[511], 15.5 / 16.0, 15.5 / 16.0);
+
+        // Stride 16 layers: six anchors per cell
+        assert_center(&anchors[512], 0.5 / 8.0, 0.5 / 8.0);
+        assert_center(&anchors[517], 0.5 / 8.0, 0.5 / 8.0);
+        assert_center(&anchors[518], 1.5 / 8.0, 0.5 / 8.0);
+        assert_center(&anchors[895], 7.5 / 8.0, 7.5 / 8.0);
+
+        let full_range = generate_anchors(&SsdAnchorOptions::blazeface_full_range());
+        assert_center(&full_range[49], 1.5 / 48.0, 1.5 / 48.0);
+    }
+
+    #[test]
+    fn variant_for_input_size() {
+        assert_eq!(SsdAnchorOptions::for_input_size(128), Some(SsdAnchorOptions::blazeface_short_range()));
+        assert_eq!(SsdAnchorOptions::for_input_size(192), Some(SsdAnchorOptions::blazeface_full_range()));
+        assert_eq!(SsdAnchorOptions::for_input_size(256), Some(SsdAnchorOptions::blazeface_back()));
+        assert_eq!(SsdAnchorOptions::for_input_size(160), None);
+    }
+
+    #[test]
+    fn scales_follow_num_layers() {
+        // Only the first two strides are used, so the second layer has max_scale
+        let options = SsdAnchorOptions {
+            num_layers: 2,
+            strides: vec![8, 16, 32],
+            min_scale: 0.2,
+            max_scale: 0.8,
+            fixed_anchor_size: false,
+            ..SsdAnchorOptions::blazeface_short_range()
+        };
+        assert!(options.validate().is_ok());
+        let anchors = generate_anchors(&options);
+        assert_eq!(anchors.len(), 2 * (16 * 16 + 8 * 8));
+
+        // Each cell has the layer's scale and the one interpolated towards the
+        // next layer, which is 1 after the last layer
+        let widths = [anchors[0].w, anchors[1].w, anchors[512].w, anchors[513].w];
+        for (width, expected) in widths.into_iter().zip([0.2, 0.4, 0.8, 0.8f32.sqrt()]) {
+            assert!((width - expected).abs() < 1e-6, "width {}, expected {}", width, expected);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_options() {
+        for options in [
+            SsdAnchorOptions::blazeface_short_range(),
+            SsdAnchorOptions::blazeface_full_range(),
+            SsdAnchorOptions::blazeface_back(),
+        ] {
+            assert!(options.validate().is_ok(), "{:?} was rejected", options);
+        }
+
+        let valid = SsdAnchorOptions::blazeface_short_range();
+        for options in [
+            SsdAnchorOptions { strides: vec![0], num_layers: 1, ..valid.clone() },
+            SsdAnchorOptions { strides: vec![], num_layers: 1, ..valid.clone() },
+            SsdAnchorOptions { num_layers: 0, ..valid.clone() },
+            SsdAnchorOptions { num_layers: 5, ..valid.clone() },
+            SsdAnchorOptions { input_size_width: 0, ..valid.clone() },
+            SsdAnchorOptions { min_scale: f32::NAN, ..valid.clone() },
+            SsdAnchorOptions { max_scale: 0.0, ..valid.clone() },
+            SsdAnchorOptions { aspect_ratios: vec![1.0, -2.0], ..valid.clone() },
+        ] {
+            assert!(options.validate().is_err(), "{:?} was accepted", options);
+        }
+    }
+}
@@ -1,19 +1,13 @@
-use std::path::Path;
-use std::sync::OnceLock;
+use visioncore_plugin::{FrameRef, Face};
+use crate::config::DetectorConfig;
+use crate::error::LocinetError;
+use crate::model::{input_index, load_interpreter, ModelInfo, TfliteInterpreter};
+use crate::preprocess::{LetterboxTransform, Preprocessor};
+use crate::anchors::{generate_anchors, Anchor, SsdAnchorOptions};
+use crate::utils::get_faces_from_anchors;
 
-use tflite::{FlatBufferModel, Interpreter, InterpreterBuilder};
-use tflite::ops::builtin::BuiltinOpResolver;
-use visioncore_plugin::{Frame, Face};
-use crate::utils::{generate_anchors, get_faces_from_anchors, normalize_image, pad_frame, resize_image};
-
-static ANCHORS: OnceLock<Vec<[f32; 4]>> = OnceLock::new();
-
-fn get_anchors() -> &'static Vec<[f32; 4]> {
-    ANCHORS.get_or_init(|| {
-        const INPUT_SIZE: i32 = 128;
-        generate_anchors(INPUT_SIZE)
-    })
-}
+// Values per anchor in the regressor output: box (4) and six keypoints (12)
+const NUM_REGRESSORS: usize = 16;
 
 pub struct BlazeFaceOutputs {
     pub faces: Vec<Face>,
@@ -23,23 +17,34 @@ impl BlazeFaceOutputs {
     pub fn new(
         deltas: &[f32],
         scores: &[f32],
-        anchors: &Vec<[f32; 4]>,
-        confidence_threshold: f32,
-        iou_threshold: f32,
-        image_h: i32,
-        image_w: i32,
-    ) -> Self {
-        assert_eq!(deltas.len(), 896 * 16, "Deltas length mismatch");
-        assert_eq!(scores.len(), 896, "Scores length mismatch");
+        anchors: &[Anchor],
+        input_size: usize,
+        config: &DetectorConfig,
+        transform: &LetterboxTransform,
+    ) -> Result<Self, LocinetError> {
+        if deltas.len() != anchors.len() * NUM_REGRESSORS {
+            return Err(LocinetError::ShapeMismatch {
+                tensor: "regressors".to_string(),
+                expected: vec![anchors.len() * NUM_REGRESSORS],
+                actual: vec![deltas.len()],
+            });
+        }
+        if scores.len() != anchors.len() {
+            return Err(LocinetError::ShapeMismatch {
+                tensor: "scores".to_string(),
+                expected: vec![anchors.len()],
+                actual: vec![scores.len()],
+            });
+        }
 
         // Pre-allocate for valid indices and scores
-        let mut valid_indices = Vec::with_capacity(896); // Worst case: all anchors are valid
-        let mut valid_scores = Vec::with_capacity(896);
+        let mut valid_indices = Vec::with_capacity(anchors.len()); // Worst case: all anchors are valid
+        let mut valid_scores = Vec::with_capacity(anchors.len());
 
         // Filter scores and compute sigmoid in one pass
         for (i, &logit) in scores.iter().enumerate() {
             let prob = 1.0 / (1.0 + (-logit).exp());
-            if prob >= confidence_threshold {
+            if prob >= config.confidence_threshold {
                 valid_indices.push(i);
                 valid_scores.push(prob);
             }
@@ -48,86 +53,142 @@ impl BlazeFaceOutputs {
         // Pre-allocate best_deltas with exact size
         let mut best_deltas = Vec::with_capacity(valid_indices.len());
         for &index in &valid_indices {
-            let start = index * 16;
-            let delta_slice = &deltas[start..start + 16];
+            let start = index * NUM_REGRESSORS;
+            let delta_slice = &deltas[start..start + NUM_REGRESSORS];
             best_deltas.push(delta_slice.to_vec()); // Still needed for adjust_anchors
         }
 
         // Build valid anchors without unnecessary cloning
-        let valid_anchors: Vec<[f32; 4]> = valid_indices.iter().map(|&i| anchors[i]).collect();
+        let valid_anchors: Vec<Anchor> = valid_indices.iter().map(|&i| anchors[i]).collect();
 
         // Get faces from anchors
-        let faces = get_faces_from_anchors(
+        let mut faces = get_faces_from_anchors(
             &valid_anchors,
             &best_deltas,
             &valid_scores,
-            128.0,
-            iou_threshold,
-            image_h,
-            image_w
-        );
+            input_size as f32,
+            config.iou_threshold,
+            config.nms_mode,
+            transform
+        )?;
+
+        // Faces come out of NMS sorted by score, so truncating keeps the best ones
+        faces.retain(|face| face.bbox[2] >= config.min_face_size && face.bbox[3] >= config.min_face_size);
+        if let Some(max_detections) = config.max_detections {
+            faces.truncate(max_detections);
+        }
 
-        Self { faces }
+        Ok(Self { faces })
     }
 }
 
-pub struct BlazeFace<'a> {
-    interpreter: Interpreter<'a, BuiltinOpResolver>,
+pub struct BlazeFace {
+    interpreter: TfliteInterpreter,
+    preprocessor: Preprocessor,
+    info: ModelInfo,
+    anchors: Vec<Anchor>,
+    // Output tensors holding the box regressors and the score logits
+    regressors_output: i32,
+    scores_output: i32,
+    config: DetectorConfig,
 }
 
-impl<'a> BlazeFace<'a> {
-    pub fn new(model_path: &Path) -> Result<Self, String> {
-        let model = FlatBufferModel::build_from_file(model_path)
-            .map_err(|e| format!("Failed to load model: {:?}", e))?;
-        let resolver = BuiltinOpResolver::default();
-        let builder = InterpreterBuilder::new(model, resolver)
-            .map_err(|e| format!("Failed to create interpreter builder: {:?}", e))?;
-        let mut interpreter = builder.build()
-            .map_err(|e| format!("Failed to build interpreter: {:?}", e))?;
-        interpreter.allocate_tensors()
-            .map_err(|e| format!("Failed to allocate tensors: {:?}", e))?;
-        Ok(BlazeFace { interpreter })
+impl BlazeFace {
+    pub fn new(config: DetectorConfig) -> Result<Self, LocinetError> {
+        config.validate()?;
+        let interpreter = load_interpreter(&config.model_path)?;
+
+        // Everything inference relies on is checked here, so a wrong model is
+        // rejected at load time rather than failing on the first frame
+        let mut info = ModelInfo::read(&interpreter, &config.model_path)?;
+        let input_size = info.input_size;
+
+        // The input size selects the BlazeFace variant
+        let anchor_options = match &config.anchors {
+            Some(options) => options.clone(),
+            None => SsdAnchorOptions::for_input_size(input_size).ok_or_else(|| LocinetError::ModelInvalid(format!(
+                "No BlazeFace variant has a {0}x{0} input; set `anchors` in the detector config",
+                input_size
+            )))?,
+        };
+        let anchors = generate_anchors(&anchor_options);
+        info.num_anchors = Some(anchors.len());
+
+        // Regressors are [1, anchors, 16] and scores [1, anchors, 1], in either order
+        if info.outputs.len() != 2 {
+            return Err(LocinetError::ModelInvalid(format!(
+                "Face detector model needs 2 outputs (regressors and scores), found {}",
+                info.outputs.len()
+            )));
+        }
+        let regressors = info.outputs.iter().position(|o| o.shape.last() == Some(&NUM_REGRESSORS)).ok_or_else(|| {
+            LocinetError::ModelInvalid(format!(
+                "Face detector model has no regressor output with {} values per anchor", NUM_REGRESSORS
+            ))
+        })?;
+        let scores = 1 - regressors;
+        info.outputs[regressors].expect_shape(&[1, anchors.len(), NUM_REGRESSORS])?;
+        info.outputs[scores].expect_shape(&[1, anchors.len(), 1])?;
+        let regressors_output = interpreter.outputs()[regressors];
+        let scores_output = interpreter.outputs()[scores];
+
+        let preprocessor = Preprocessor::new(input_size, config.normalization);
+        Ok(BlazeFace { interpreter, preprocessor, info, anchors, regressors_output, scores_output, config })
     }
 
-    pub fn detect_faces(&mut self, frame: &Frame) -> Option<Vec<Face>> {
-        let padded_image = pad_frame(frame);
-        let resized_image = resize_image(&padded_image, 128);
-        let normalized_image = normalize_image(&resized_image);
+    pub fn model_info(&self) -> &ModelInfo {
+        &self.info
+    }
 
-        let input_index = self.interpreter.inputs()[0];
-        let input_tensor_data = self.interpreter.tensor_data_mut(input_index)
-            .expect("Failed to get input tensor data");
+    pub fn config(&self) -> &DetectorConfig {
+        &self.config
+    }
 
-        let expected_elements = 1 * 128 * 128 * 3;
-        assert_eq!(input_tensor_data.len(), expected_elements, "Input tensor size mismatch");
-        input_tensor_data.copy_from_slice(&normalized_image.data);
+    // Apply new thresholds, limits and normalization from the next frame on.
+    // The model itself stays loaded, so `model_path` and `anchors` cannot change.
+    pub fn set_config(&mut self, config: DetectorConfig) -> Result<(), LocinetError> {
+        config.validate()?;
+        if config.model_path != self.config.model_path {
+            return Err(LocinetError::InvalidConfig(format!(
+                "Cannot switch model from {:?} to {:?} without reloading the detector",
+                self.config.model_path, config.model_path
+            )));
+        }
+        if config.anchors != self.config.anchors {
+            return Err(LocinetError::InvalidConfig(
+                "Cannot change anchors without reloading the detector".to_string(),
+            ));
+        }
+        self.preprocessor.set_normalization(config.normalization);
+        self.config = config;
+        Ok(())
+    }
 
-        self.interpreter.invoke().expect("Inference failed");
+    pub fn detect_faces(&mut self, frame: &FrameRef) -> Result<Vec<Face>, LocinetError> {
+        frame.validate().map_err(LocinetError::InvalidFrame)?;
 
-        let output_delta_index = self.interpreter.outputs()[0];
-        let output_score_index = self.interpreter.outputs()[1];
-        let deltas: &[f32] = self.interpreter.tensor_data(output_delta_index)
-            .expect("Failed to get deltas");
-        let scores: &[f32] = self.interpreter.tensor_data(output_score_index)
-            .expect("Failed to get scores");
+        let input_index = input_index(&self.interpreter, 0)?;
+        let input_tensor_data = self.interpreter.tensor_data_mut(input_index)
+            .map_err(|e| LocinetError::InferenceFailed(format!("Failed to get input tensor data: {:?}", e)))?;
+        // Letterbox, resample and normalize straight into the input tensor
+        self.preprocessor.run(frame, input_tensor_data)?;
+
+        self.interpreter.invoke()
+            .map_err(|e| LocinetError::InferenceFailed(format!("{:?}", e)))?;
+
+        let deltas: &[f32] = self.interpreter.tensor_data(self.regressors_output)
+            .map_err(|e| LocinetError::InferenceFailed(format!("Failed to get deltas: {:?}", e)))?;
+        let scores: &[f32] = self.interpreter.tensor_data(self.scores_output)
+            .map_err(|e| LocinetError::InferenceFailed(format!("Failed to get scores: {:?}", e)))?;
 
         let outputs = BlazeFaceOutputs::new(
             deltas,
             scores,
-            get_anchors(),
-            0.5,
-            0.2,
-            frame.height as i32,
-            frame.width as i32,
-        );
-
-        // println!("BlazeFaceOutputs faces length: {}", outputs.faces.len());
-        if outputs.faces.is_empty() {
-            // println!("Returning None: no faces detected");
-            None
-        } else {
-            // println!("Returning Some(faces) with length: {}", outputs.faces.len());
-            Some(outputs.faces)
-        }
+            &self.anchors,
+            self.info.input_size,
+            &self.config,
+            &self.preprocessor.transform(),
+        )?;
+        Ok(outputs.faces)
     }
-}
\ No newline at end of file
+}
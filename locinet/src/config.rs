@@ -0,0 +1,134 @@
+use std::env;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::anchors::SsdAnchorOptions;
+use crate::error::LocinetError;
+
+// Face detector settings. Everything except `model_path` and `anchors` can be
+// changed on a running detector with `set_config`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DetectorConfig {
+    pub model_path: PathBuf,
+    // Minimum face probability for a detection to be kept
+    pub confidence_threshold: f32,
+    // Overlapping detections above this IoU are suppressed
+    pub iou_threshold: f32,
+    pub nms_mode: NmsMode,
+    // Keep at most this many faces per frame, highest scores first; None keeps all
+    pub max_detections: Option<usize>,
+    // Drop faces whose box is smaller than this many frame pixels on either side
+    pub min_face_size: f32,
+    pub normalization: Normalization,
+    // Anchor layout of the model. None picks the BlazeFace variant matching
+    // the model's input size (128 short-range, 192 full-range, 256 back camera);
+    // set it for other SSD-style models.
+    pub anchors: Option<SsdAnchorOptions>,
+}
+
+impl Default for DetectorConfig {
+    fn default() -> Self {
+        DetectorConfig {
+            model_path: default_model_path(),
+            confidence_threshold: 0.5,
+            iou_threshold: 0.2,
+            nms_mode: NmsMode::default(),
+            max_detections: None,
+            min_face_size: 0.0,
+            normalization: Normalization::default(),
+            anchors: None,
+        }
+    }
+}
+
+// LOCINET_MODEL_PATH still overrides the bundled model when no path is configured
+fn default_model_path() -> PathBuf {
+    env::var("LOCINET_MODEL_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("locinet/models/face_detector.tflite"))
+}
+
+impl DetectorConfig {
+    pub fn validate(&self) -> Result<(), LocinetError> {
+        if !(0.0..=1.0).contains(&self.confidence_threshold) {
+            return Err(LocinetError::InvalidConfig(format!("confidence_threshold must be in [0, 1], got {}", self.confidence_threshold)));
+        }
+        if !(0.0..=1.0).contains(&self.iou_threshold) {
+            return Err(LocinetError::InvalidConfig(format!("iou_threshold must be in [0, 1], got {}", self.iou_threshold)));
+        }
+        if !(self.min_face_size.is_finite() && self.min_face_size >= 0.0) {
+            return Err(LocinetError::InvalidConfig(format!("min_face_size must be a non-negative number, got {}", self.min_face_size)));
+        }
+        if self.max_detections == Some(0) {
+            return Err(LocinetError::InvalidConfig("max_detections must be at least 1, or null to keep all faces".to_string()));
+        }
+        if let Some(anchors) = &self.anchors {
+            anchors.validate()?;
+        }
+        Ok(())
+    }
+}
+
+// How overlapping detections of the same face are reduced to one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NmsMode {
+    // Keep the highest-scoring detection, drop the others
+    #[default]
+    Greedy,
+    // Average the overlapping detections weighted by score, as MediaPipe
+    // does; steadier boxes and keypoints from frame to frame
+    Weighted,
+}
+
+// Range the model expects its input pixels in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Normalization {
+    // [0, 1]
+    #[default]
+    Unit,
+    // [-1, 1]
+    Symmetric,
+}
+
+impl Normalization {
+    #[inline]
+    pub fn apply(self, value: u8) -> f32 {
+        self.apply_f32(value as f32)
+    }
+
+    // Same for interpolated samples in [0, 255]
+    #[inline]
+    pub fn apply_f32(self, value: f32) -> f32 {
+        match self {
+            Normalization::Unit => value / 255.0,
+            Normalization::Symmetric => value / 127.5 - 1.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_invalid_configs() {
+        assert!(DetectorConfig::default().validate().is_ok());
+        for config in [
+            DetectorConfig { confidence_threshold: 1.5, ..DetectorConfig::default() },
+            DetectorConfig { iou_threshold: f32::NAN, ..DetectorConfig::default() },
+            DetectorConfig { min_face_size: -1.0, ..DetectorConfig::default() },
+            DetectorConfig { min_face_size: f32::NAN, ..DetectorConfig::default() },
+            DetectorConfig { max_detections: Some(0), ..DetectorConfig::default() },
+            DetectorConfig {
+                anchors: Some(SsdAnchorOptions { strides: vec![0], num_layers: 1, ..SsdAnchorOptions::blazeface_short_range() }),
+                ..DetectorConfig::default()
+            },
+        ] {
+            assert!(config.validate().is_err(), "{:?} was accepted", config);
+        }
+    }
+}
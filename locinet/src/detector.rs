@@ -0,0 +1,44 @@
+use visioncore_plugin::{Face, FrameRef};
+
+use crate::blazeface::BlazeFace;
+use crate::config::DetectorConfig;
+use crate::error::LocinetError;
+use crate::model::ModelInfo;
+
+// A face detector with its own interpreter. Each camera (or worker thread)
+// owns one, so detectors never contend for a lock and can run with
+// different models and configs side by side.
+pub struct FaceDetector {
+    blazeface: BlazeFace,
+}
+
+impl FaceDetector {
+    pub fn new(config: DetectorConfig) -> Result<Self, LocinetError> {
+        Ok(FaceDetector { blazeface: BlazeFace::new(config)? })
+    }
+
+    // Load another detector with the same model and config, e.g. to fill a
+    // pool of workers. Interpreters cannot be shared, so the model is loaded again.
+    pub fn try_clone(&self) -> Result<Self, LocinetError> {
+        Self::new(self.config().clone())
+    }
+
+    // Tensor layout of the loaded model, validated at load time
+    pub fn model_info(&self) -> &ModelInfo {
+        self.blazeface.model_info()
+    }
+
+    pub fn config(&self) -> &DetectorConfig {
+        self.blazeface.config()
+    }
+
+    // Change thresholds, limits or normalization without reloading the model
+    pub fn set_config(&mut self, config: DetectorConfig) -> Result<(), LocinetError> {
+        self.blazeface.set_config(config)
+    }
+
+    // Faces in the frame, best first; empty when there are none
+    pub fn detect_faces(&mut self, frame: &FrameRef) -> Result<Vec<Face>, LocinetError> {
+        self.blazeface.detect_faces(frame)
+    }
+}
@@ -0,0 +1,44 @@
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+
+// Everything that can go wrong in locinet. Bad frames and failed inferences
+// are reported per call, so the caller can drop the frame and carry on.
+#[derive(Debug)]
+pub enum LocinetError {
+    // No model file at the configured path
+    ModelNotFound(PathBuf),
+    // The file is not a usable TFLite model, or the interpreter cannot be built
+    ModelInvalid(String),
+    // A model tensor does not have the shape locinet was written for
+    ShapeMismatch {
+        tensor: String,
+        expected: Vec<usize>,
+        actual: Vec<usize>,
+    },
+    // The frame failed `FrameRef::validate`
+    InvalidFrame(String),
+    InferenceFailed(String),
+    // A `DetectorConfig` value is out of range
+    InvalidConfig(String),
+    // The face gallery file cannot be read, parsed or written
+    Gallery(String),
+}
+
+impl fmt::Display for LocinetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LocinetError::ModelNotFound(path) => write!(f, "Model file not found at {:?}", path),
+            LocinetError::ModelInvalid(reason) => write!(f, "Invalid model: {}", reason),
+            LocinetError::ShapeMismatch { tensor, expected, actual } => {
+                write!(f, "Tensor {} has shape {:?}, expected {:?}", tensor, actual, expected)
+            }
+            LocinetError::InvalidFrame(reason) => write!(f, "Invalid frame: {}", reason),
+            LocinetError::InferenceFailed(reason) => write!(f, "Inference failed: {}", reason),
+            LocinetError::InvalidConfig(reason) => write!(f, "Invalid detector config: {}", reason),
+            LocinetError::Gallery(reason) => write!(f, "Face gallery error: {}", reason),
+        }
+    }
+}
+
+impl Error for LocinetError {}
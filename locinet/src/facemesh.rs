@@ -0,0 +1,114 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use visioncore_plugin::{Face, FaceKeypoint, FrameRef, Landmark};
+use crate::error::LocinetError;
+use crate::model::{input_index, load_interpreter, ModelInfo, TfliteInterpreter};
+use crate::preprocess::RotatedRect;
+
+pub const NUM_MESH_LANDMARKS: usize = 468;
+
+// The face crop is the detection box enlarged by this factor, as in MediaPipe
+const ROI_SCALE: f32 = 1.5;
+
+// LOCINET_LANDMARK_MODEL_PATH overrides the bundled face mesh model
+pub(crate) fn default_model_path() -> PathBuf {
+    env::var("LOCINET_LANDMARK_MODEL_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("locinet/models/face_landmarks_detector.tflite"))
+}
+
+pub struct FaceLandmarks {
+    // 468 mesh points; x and y in frame pixels, z in pixels relative to the
+    // face center (smaller is closer to the camera)
+    pub landmarks: Vec<Landmark>,
+    // Probability that the crop actually contains a face
+    pub presence: f32,
+}
+
+pub struct FaceMesh {
+    interpreter: TfliteInterpreter,
+    input_size: usize,
+    // Output tensors holding the landmarks and the presence logit
+    landmarks_output: i32,
+    presence_output: i32,
+}
+
+impl FaceMesh {
+    pub fn new(model_path: &Path) -> Result<Self, LocinetError> {
+        let interpreter = load_interpreter(model_path)?;
+
+        // Square NHWC RGB input; the bundled model uses 256x256
+        let info = ModelInfo::read(&interpreter, model_path)?;
+        let input_size = info.input_size;
+
+        // Landmarks come as (x, y, z) triples in crop pixels; the first scalar
+        // output is the face presence logit
+        let mut landmarks_output = None;
+        let mut presence_output = None;
+        for (output, &index) in info.outputs.iter().zip(interpreter.outputs()) {
+            let len: usize = output.shape.iter().product();
+            if len >= NUM_MESH_LANDMARKS * 3 && landmarks_output.is_none() {
+                landmarks_output = Some(index);
+            } else if len == 1 && presence_output.is_none() {
+                presence_output = Some(index);
+            }
+        }
+        let (Some(landmarks_output), Some(presence_output)) = (landmarks_output, presence_output) else {
+            return Err(LocinetError::ModelInvalid(
+                "Face mesh model needs a landmarks output and a presence output".to_string(),
+            ));
+        };
+
+        Ok(FaceMesh { interpreter, input_size, landmarks_output, presence_output })
+    }
+
+    // Upright square crop around a detected face. The rotation levels the
+    // line between the eyes.
+    pub fn face_roi(face: &Face) -> RotatedRect {
+        let [x, y, w, h] = face.bbox;
+        let [right_eye_x, right_eye_y] = face.keypoint(FaceKeypoint::RightEye);
+        let [left_eye_x, left_eye_y] = face.keypoint(FaceKeypoint::LeftEye);
+        let rotation = (left_eye_y - right_eye_y).atan2(left_eye_x - right_eye_x);
+
+        RotatedRect {
+            center_x: x + w / 2.0,
+            center_y: y + h / 2.0,
+            size: w.max(h) * ROI_SCALE,
+            rotation,
+        }
+    }
+
+    pub fn detect_landmarks(&mut self, frame: &FrameRef, face: &Face) -> Result<FaceLandmarks, LocinetError> {
+        frame.validate().map_err(LocinetError::InvalidFrame)?;
+        let roi = Self::face_roi(face);
+
+        let input_index = input_index(&self.interpreter, 0)?;
+        let input_tensor_data = self.interpreter.tensor_data_mut(input_index)
+            .map_err(|e| LocinetError::InferenceFailed(format!("Failed to get input tensor data: {:?}", e)))?;
+        roi.crop_into(frame, self.input_size, input_tensor_data)?;
+
+        self.interpreter.invoke()
+            .map_err(|e| LocinetError::InferenceFailed(format!("{:?}", e)))?;
+
+        let landmark_data: &[f32] = self.interpreter.tensor_data(self.landmarks_output)
+            .map_err(|e| LocinetError::InferenceFailed(format!("Failed to get landmarks: {:?}", e)))?;
+        let presence_data: &[f32] = self.interpreter.tensor_data(self.presence_output)
+            .map_err(|e| LocinetError::InferenceFailed(format!("Failed to get face presence: {:?}", e)))?;
+        let presence = 1.0 / (1.0 + (-presence_data[0]).exp());
+
+        let scale = roi.size / self.input_size as f32;
+        let landmarks = landmark_data
+            .chunks_exact(3)
+            .take(NUM_MESH_LANDMARKS)
+            .map(|point| {
+                let u = point[0] / self.input_size as f32;
+                let v = point[1] / self.input_size as f32;
+                let [x, y] = roi.to_frame(u, v);
+                Landmark { x, y, z: point[2] * scale }
+            })
+            .collect();
+
+        Ok(FaceLandmarks { landmarks, presence })
+    }
+}
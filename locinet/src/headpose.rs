@@ -0,0 +1,482 @@
+// Head pose from 2D face points: the pose of a canonical 3D face model is
+// solved by PnP (Levenberg-Marquardt on the reprojection error) against the
+// BlazeFace keypoints or a few face mesh landmarks.
+//
+// Model and camera coordinates: x to the right of the image, y down, z away
+// from the camera. The canonical model faces the camera with the nose tip at
+// the origin, in millimetres.
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use visioncore_plugin::{Face, FrameRef, NUM_FACE_KEYPOINTS};
+
+use crate::error::LocinetError;
+use crate::facemesh::{self, FaceLandmarks, FaceMesh};
+
+// Canonical positions of the BlazeFace keypoints, indexed by FaceKeypoint
+const KEYPOINT_MODEL: [[f64; 3]; NUM_FACE_KEYPOINTS] = [
+    [-32.0, -35.0, 20.0], // right eye
+    [32.0, -35.0, 20.0],  // left eye
+    [0.0, 0.0, 0.0],      // nose tip
+    [0.0, 30.0, 18.0],    // mouth center
+    [-75.0, -15.0, 95.0], // right ear tragion
+    [75.0, -15.0, 95.0],  // left ear tragion
+];
+
+// Face mesh landmark indices and their canonical positions
+const LANDMARK_MODEL: [(usize, [f64; 3]); 6] = [
+    (1, [0.0, 0.0, 0.0]),       // nose tip
+    (152, [0.0, 66.0, 13.0]),   // chin
+    (33, [-45.0, -34.0, 27.0]), // right eye outer corner
+    (263, [45.0, -34.0, 27.0]), // left eye outer corner
+    (61, [-30.0, 30.0, 25.0]),  // right mouth corner
+    (291, [30.0, 30.0, 25.0]),  // left mouth corner
+];
+
+const MAX_ITERATIONS: usize = 30;
+
+// RMS reprojection error, as a fraction of the face size, at which the fit
+// quality part of the confidence drops to one half
+const FIT_TOLERANCE: f64 = 0.05;
+
+// Pinhole camera parameters in frame pixels
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CameraIntrinsics {
+    pub fx: f32,
+    pub fy: f32,
+    pub cx: f32,
+    pub cy: f32,
+}
+
+impl CameraIntrinsics {
+    // Rough intrinsics for an uncalibrated camera: focal length equal to the
+    // long side of the frame (about 53 degrees of view), centered principal point
+    pub fn approximate(frame_w: i32, frame_h: i32) -> Self {
+        let focal = frame_w.max(frame_h) as f32;
+        CameraIntrinsics {
+            fx: focal,
+            fy: focal,
+            cx: frame_w as f32 / 2.0,
+            cy: frame_h as f32 / 2.0,
+        }
+    }
+}
+
+// 2D points the pose is solved from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PoseSource {
+    // The six BlazeFace keypoints; no extra model to run
+    #[default]
+    Keypoints,
+    // Face mesh landmarks; more accurate but runs the mesh model on every face
+    Landmarks,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeadPoseConfig {
+    pub source: PoseSource,
+    // Face mesh model, used with the `landmarks` source
+    pub landmark_model_path: PathBuf,
+    // None approximates them from the frame size
+    pub intrinsics: Option<CameraIntrinsics>,
+}
+
+impl Default for HeadPoseConfig {
+    fn default() -> Self {
+        HeadPoseConfig {
+            source: PoseSource::default(),
+            landmark_model_path: facemesh::default_model_path(),
+            intrinsics: None,
+        }
+    }
+}
+
+impl HeadPoseConfig {
+    pub fn validate(&self) -> Result<(), LocinetError> {
+        if let Some(intrinsics) = &self.intrinsics {
+            if intrinsics.fx <= 0.0 || intrinsics.fy <= 0.0 {
+                return Err(LocinetError::InvalidConfig("focal lengths must be positive".to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Head orientation in degrees; all zero when the face looks straight along the
+// camera axis
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct HeadPose {
+    // Positive when the subject turns to their left
+    pub yaw: f32,
+    // Positive when the subject looks up
+    pub pitch: f32,
+    // Positive when the subject tilts their head toward their left shoulder
+    pub roll: f32,
+    // Detection score (or mesh presence) times the quality of the model fit, in [0, 1]
+    pub confidence: f32,
+}
+
+pub struct HeadPoseEstimator {
+    config: HeadPoseConfig,
+    // Loaded only for the `landmarks` source
+    facemesh: Option<FaceMesh>,
+}
+
+impl HeadPoseEstimator {
+    pub fn new(config: HeadPoseConfig) -> Result<Self, LocinetError> {
+        config.validate()?;
+        let facemesh = match config.source {
+            PoseSource::Keypoints => None,
+            PoseSource::Landmarks => Some(FaceMesh::new(&config.landmark_model_path)?),
+        };
+        Ok(HeadPoseEstimator { config, facemesh })
+    }
+
+    pub fn config(&self) -> &HeadPoseConfig {
+        &self.config
+    }
+
+    // Pose of a face detected in `frame`, from the configured source. None
+    // when the model cannot be fitted to the face.
+    pub fn estimate(&mut self, frame: &FrameRef, face: &Face) -> Result<Option<HeadPose>, LocinetError> {
+        match &mut self.facemesh {
+            Some(facemesh) => {
+                let mesh = facemesh.detect_landmarks(frame, face)?;
+                Ok(self.estimate_from_landmarks(face, &mesh))
+            }
+            None => Ok(self.estimate_from_keypoints(face)),
+        }
+    }
+
+    pub fn estimate_from_keypoints(&self, face: &Face) -> Option<HeadPose> {
+        let image: Vec<[f64; 2]> = face.keypoints.iter().map(|&[x, y]| [x as f64, y as f64]).collect();
+        self.solve(face, &KEYPOINT_MODEL, &image, face.score)
+    }
+
+    pub fn estimate_from_landmarks(&self, face: &Face, mesh: &FaceLandmarks) -> Option<HeadPose> {
+        let mut model = Vec::with_capacity(LANDMARK_MODEL.len());
+        let mut image = Vec::with_capacity(LANDMARK_MODEL.len());
+        for &(index, point) in &LANDMARK_MODEL {
+            let landmark = mesh.landmarks.get(index)?;
+            model.push(point);
+            image.push([landmark.x as f64, landmark.y as f64]);
+        }
+        self.solve(face, &model, &image, mesh.presence)
+    }
+
+    fn solve(&self, face: &Face, model: &[[f64; 3]], image: &[[f64; 2]], score: f32) -> Option<HeadPose> {
+        let intrinsics = self.config.intrinsics
+            .unwrap_or_else(|| CameraIntrinsics::approximate(face.frame_w, face.frame_h));
+        let camera = Camera {
+            fx: intrinsics.fx as f64,
+            fy: intrinsics.fy as f64,
+            cx: intrinsics.cx as f64,
+            cy: intrinsics.cy as f64,
+        };
+        let (rotation, rms) = solve_pnp(model, image, &camera)?;
+
+        // R = Ry(a) * Rx(b) * Rz(c), turned into the signs documented on HeadPose
+        let yaw = -rotation[0][2].atan2(rotation[2][2]);
+        let pitch = rotation[1][2].clamp(-1.0, 1.0).asin();
+        let roll = rotation[1][0].atan2(rotation[1][1]);
+
+        let size = face.bbox[2].max(face.bbox[3]).max(1.0) as f64;
+        let error = rms / (FIT_TOLERANCE * size);
+        let fit = 1.0 / (1.0 + error * error);
+
+        Some(HeadPose {
+            yaw: yaw.to_degrees() as f32,
+            pitch: pitch.to_degrees() as f32,
+            roll: roll.to_degrees() as f32,
+            confidence: (score as f64 * fit).clamp(0.0, 1.0) as f32,
+        })
+    }
+}
+
+struct Camera {
+    fx: f64,
+    fy: f64,
+    cx: f64,
+    cy: f64,
+}
+
+type Matrix3 = [[f64; 3]; 3];
+
+// Pose parameters: rotation vector followed by translation
+type Pose = [f64; 6];
+
+// Rotation of the model into camera coordinates and the RMS reprojection error
+// in pixels. None when the fit fails or puts the face behind the camera.
+fn solve_pnp(model: &[[f64; 3]], image: &[[f64; 2]], camera: &Camera) -> Option<(Matrix3, f64)> {
+    let mut pose = initial_pose(model, image, camera)?;
+    let mut residuals = reproject(model, image, camera, &pose)?;
+    let mut cost = dot(&residuals, &residuals);
+    let mut damping = 1e-3;
+
+    for _ in 0..MAX_ITERATIONS {
+        let jacobian = jacobian(model, image, camera, &pose, &residuals)?;
+
+        // Normal equations J^T J delta = -J^T r
+        let mut normal = [[0.0; 6]; 6];
+        let mut gradient = [0.0; 6];
+        for (row, &residual) in jacobian.iter().zip(&residuals) {
+            for i in 0..6 {
+                gradient[i] -= row[i] * residual;
+                for j in 0..6 {
+                    normal[i][j] += row[i] * row[j];
+                }
+            }
+        }
+
+        // Levenberg-Marquardt: grow the damping until a step lowers the cost
+        let mut improved = false;
+        while damping < 1e8 {
+            let mut damped = normal;
+            for (i, row) in damped.iter_mut().enumerate() {
+                row[i] += damping * normal[i][i].max(1e-9);
+            }
+            let Some(step) = solve6(damped, gradient) else {
+                damping *= 10.0;
+                continue;
+            };
+            let mut candidate = pose;
+            for (value, delta) in candidate.iter_mut().zip(&step) {
+                *value += delta;
+            }
+            match reproject(model, image, camera, &candidate) {
+                Some(candidate_residuals) if dot(&candidate_residuals, &candidate_residuals) < cost => {
+                    let candidate_cost = dot(&candidate_residuals, &candidate_residuals);
+                    let converged = cost - candidate_cost < 1e-10 * cost || dot(&step, &step) < 1e-16;
+                    pose = candidate;
+                    residuals = candidate_residuals;
+                    cost = candidate_cost;
+                    damping = (damping / 10.0).max(1e-9);
+                    improved = !converged;
+                    break;
+                }
+                _ => damping *= 10.0,
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+
+    let rms = (cost / model.len() as f64).sqrt();
+    Some((rotation_matrix(&pose), rms))
+}
+
+// Frontal pose at the distance where the model matches the spread of the points
+fn initial_pose(model: &[[f64; 3]], image: &[[f64; 2]], camera: &Camera) -> Option<Pose> {
+    let n = model.len() as f64;
+    let model_center = [
+        model.iter().map(|p| p[0]).sum::<f64>() / n,
+        model.iter().map(|p| p[1]).sum::<f64>() / n,
+    ];
+    let image_center = [
+        image.iter().map(|p| p[0]).sum::<f64>() / n,
+        image.iter().map(|p| p[1]).sum::<f64>() / n,
+    ];
+    let model_spread: f64 = model.iter().map(|p| (p[0] - model_center[0]).hypot(p[1] - model_center[1])).sum();
+    let image_spread: f64 = image.iter().map(|p| (p[0] - image_center[0]).hypot(p[1] - image_center[1])).sum();
+    if image_spread <= f64::EPSILON {
+        return None;
+    }
+
+    let z = camera.fx * model_spread / image_spread;
+    Some([
+        0.0,
+        0.0,
+        0.0,
+        (image_center[0] - camera.cx) * z / camera.fx - model_center[0],
+        (image_center[1] - camera.cy) * z / camera.fy - model_center[1],
+        z,
+    ])
+}
+
+// Projected minus observed point coordinates, [du0, dv0, du1, ...]
+fn reproject(model: &[[f64; 3]], image: &[[f64; 2]], camera: &Camera, pose: &Pose) -> Option<Vec<f64>> {
+    let rotation = rotation_matrix(pose);
+    let mut residuals = Vec::with_capacity(model.len() * 2);
+    for (point, observed) in model.iter().zip(image) {
+        let p = [
+            dot(&rotation[0], point) + pose[3],
+            dot(&rotation[1], point) + pose[4],
+            dot(&rotation[2], point) + pose[5],
+        ];
+        if p[2] <= f64::EPSILON {
+            return None;
+        }
+        residuals.push(camera.fx * p[0] / p[2] + camera.cx - observed[0]);
+        residuals.push(camera.fy * p[1] / p[2] + camera.cy - observed[1]);
+    }
+    Some(residuals)
+}
+
+// Forward-difference Jacobian of the residuals with respect to the pose
+fn jacobian(model: &[[f64; 3]], image: &[[f64; 2]], camera: &Camera, pose: &Pose, residuals: &[f64]) -> Option<Vec<Pose>> {
+    let mut jacobian = vec![[0.0; 6]; residuals.len()];
+    for i in 0..6 {
+        let step = if i < 3 { 1e-6 } else { 1e-6 * pose[5].abs().max(1.0) };
+        let mut shifted = *pose;
+        shifted[i] += step;
+        let shifted_residuals = reproject(model, image, camera, &shifted)?;
+        for (row, (after, before)) in jacobian.iter_mut().zip(shifted_residuals.iter().zip(residuals)) {
+            row[i] = (after - before) / step;
+        }
+    }
+    Some(jacobian)
+}
+
+// Rodrigues' formula for the rotation vector in pose[0..3]
+fn rotation_matrix(pose: &Pose) -> Matrix3 {
+    let [rx, ry, rz] = [pose[0], pose[1], pose[2]];
+    let theta = (rx * rx + ry * ry + rz * rz).sqrt();
+    if theta < 1e-12 {
+        return [[1.0, -rz, ry], [rz, 1.0, -rx], [-ry, rx, 1.0]];
+    }
+    let [x, y, z] = [rx / theta, ry / theta, rz / theta];
+    let (s, c) = theta.sin_cos();
+    let t = 1.0 - c;
+    [
+        [c + x * x * t, x * y * t - z * s, x * z * t + y * s],
+        [y * x * t + z * s, c + y * y * t, y * z * t - x * s],
+        [z * x * t - y * s, z * y * t + x * s, c + z * z * t],
+    ]
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+// Gaussian elimination with partial pivoting; None if the system is singular
+fn solve6(mut a: [[f64; 6]; 6], mut b: [f64; 6]) -> Option<[f64; 6]> {
+    for col in 0..6 {
+        let pivot = (col..6).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        let pivot_row = a[col];
+        for row in col + 1..6 {
+            let factor = a[row][col] / pivot_row[col];
+            for (value, pivot) in a[row].iter_mut().zip(&pivot_row).skip(col) {
+                *value -= factor * pivot;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+    let mut x = [0.0; 6];
+    for row in (0..6).rev() {
+        let sum: f64 = (row + 1..6).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    Some(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use visioncore_plugin::FaceKeypoint::{LeftEye, NoseTip, RightEye};
+
+    const FRAME_W: i32 = 640;
+    const FRAME_H: i32 = 480;
+
+    fn multiply(a: &Matrix3, b: &Matrix3) -> Matrix3 {
+        let mut product = [[0.0; 3]; 3];
+        for (i, row) in product.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+            }
+        }
+        product
+    }
+
+    // Rotation for a pose in degrees with the signs documented on HeadPose:
+    // R = Ry(-yaw) * Rx(-pitch) * Rz(roll)
+    fn rotation(yaw: f64, pitch: f64, roll: f64) -> Matrix3 {
+        let (a, b, c) = (-yaw.to_radians(), -pitch.to_radians(), roll.to_radians());
+        let ry = [[a.cos(), 0.0, a.sin()], [0.0, 1.0, 0.0], [-a.sin(), 0.0, a.cos()]];
+        let rx = [[1.0, 0.0, 0.0], [0.0, b.cos(), -b.sin()], [0.0, b.sin(), b.cos()]];
+        let rz = [[c.cos(), -c.sin(), 0.0], [c.sin(), c.cos(), 0.0], [0.0, 0.0, 1.0]];
+        multiply(&multiply(&ry, &rx), &rz)
+    }
+
+    // Face whose keypoints are the model rotated by `rotation`, moved by
+    // `translation` (mm) and projected with the approximate intrinsics
+    fn project(rotation: &Matrix3, translation: [f64; 3]) -> Face {
+        let intrinsics = CameraIntrinsics::approximate(FRAME_W, FRAME_H);
+        let mut keypoints = [[0.0f32; 2]; NUM_FACE_KEYPOINTS];
+        for (keypoint, point) in keypoints.iter_mut().zip(&KEYPOINT_MODEL) {
+            let p: Vec<f64> = (0..3).map(|k| dot(&rotation[k], point) + translation[k]).collect();
+            *keypoint = [
+                (intrinsics.fx as f64 * p[0] / p[2] + intrinsics.cx as f64) as f32,
+                (intrinsics.fy as f64 * p[1] / p[2] + intrinsics.cy as f64) as f32,
+            ];
+        }
+
+        let x_min = keypoints.iter().map(|k| k[0]).fold(f32::INFINITY, f32::min);
+        let x_max = keypoints.iter().map(|k| k[0]).fold(f32::NEG_INFINITY, f32::max);
+        let y_min = keypoints.iter().map(|k| k[1]).fold(f32::INFINITY, f32::min);
+        let y_max = keypoints.iter().map(|k| k[1]).fold(f32::NEG_INFINITY, f32::max);
+        let bbox = [y_min, x_min, y_max, x_max];
+        let center = [(y_min + y_max) / 2.0, (x_min + x_max) / 2.0];
+        Face::new(bbox, bbox, center, keypoints, 0.9, FRAME_H, FRAME_W)
+    }
+
+    #[test]
+    fn keypoint_pose_round_trip() {
+        let estimator = HeadPoseEstimator::new(HeadPoseConfig::default()).unwrap();
+        let poses = [
+            (0.0, 0.0, 0.0),
+            (30.0, 0.0, 0.0),
+            (-30.0, 0.0, 0.0),
+            (0.0, 20.0, 0.0),
+            (0.0, -20.0, 0.0),
+            (0.0, 0.0, 15.0),
+            (0.0, 0.0, -15.0),
+            (-40.0, -15.0, 10.0),
+            (50.0, 10.0, -20.0),
+        ];
+        for (yaw, pitch, roll) in poses {
+            let face = project(&rotation(yaw, pitch, roll), [40.0, -20.0, 600.0]);
+            let pose = estimator.estimate_from_keypoints(&face).unwrap();
+
+            let expected = [yaw, pitch, roll];
+            let actual = [pose.yaw as f64, pose.pitch as f64, pose.roll as f64];
+            for (expected, actual) in expected.iter().zip(&actual) {
+                assert!((expected - actual).abs() < 0.5, "{:?} solved as {:?}", expected, actual);
+                assert!(*expected == 0.0 || expected.signum() == actual.signum(), "{:?} solved as {:?}", expected, actual);
+            }
+            assert!(pose.confidence > 0.85, "confidence {} for {:?}", pose.confidence, expected);
+        }
+    }
+
+    // The signs follow what the subject does, as seen in the image
+    #[test]
+    fn pose_signs_match_the_image() {
+        let estimator = HeadPoseEstimator::new(HeadPoseConfig::default()).unwrap();
+        // Turning to their left moves the nose toward the left eye, which is
+        // on the right of the image
+        let face = project(&rotation(25.0, 0.0, 0.0), [0.0, 0.0, 600.0]);
+        let eyes_x = (face.keypoint(RightEye)[0] + face.keypoint(LeftEye)[0]) / 2.0;
+        assert!(face.keypoint(NoseTip)[0] > eyes_x);
+        assert!(estimator.estimate_from_keypoints(&face).unwrap().yaw > 0.0);
+
+        // Looking up moves the nose up toward the eyes
+        let level = project(&rotation(0.0, 0.0, 0.0), [0.0, 0.0, 600.0]);
+        let face = project(&rotation(0.0, 20.0, 0.0), [0.0, 0.0, 600.0]);
+        let nose_to_eye = |face: &Face| face.keypoint(NoseTip)[1] - face.keypoint(RightEye)[1];
+        assert!(nose_to_eye(&face) < nose_to_eye(&level));
+        assert!(estimator.estimate_from_keypoints(&face).unwrap().pitch > 0.0);
+
+        // Tilting toward the left shoulder lowers the left eye in the image
+        let face = project(&rotation(0.0, 0.0, 15.0), [0.0, 0.0, 600.0]);
+        assert!(face.keypoint(LeftEye)[1] > face.keypoint(RightEye)[1]);
+        assert!(estimator.estimate_from_keypoints(&face).unwrap().roll > 0.0);
+    }
+}
@@ -1,47 +1,177 @@
+mod align;
+mod anchors;
+mod config;
+mod error;
+mod model;
 mod utils;
+mod preprocess;
+mod recognition;
 mod blazeface;
+mod detector;
+mod facemesh;
+mod headpose;
+mod smoothing;
+mod tracker;
 
-use visioncore_plugin::{Frame, Face};
-use blazeface::BlazeFace;
-use std::sync::{Mutex, OnceLock};
-use std::path::PathBuf;
-use std::env;
-
-// Global BlazeFace instance (initialized once)
-static BLAZEFACE: OnceLock<Mutex<BlazeFace<'static>>> = OnceLock::new();
-
-fn get_blazeface() -> &'static Mutex<BlazeFace<'static>> {
-    BLAZEFACE.get_or_init(|| {
-        // Read the model path from an environment variable, with a fallback
-        let model_path = env::var("LOCINET_MODEL_PATH")
-            .map(PathBuf::from)
-            .unwrap_or_else(|_| {
-                // Fallback to a relative path
-                let mut path = PathBuf::from("locinet/models");
-                path.push("face_detector.tflite");
-                path
-            });
-
-        // Ensure the model file exists
-        if !model_path.exists() {
-            eprintln!("BlazeFace model file not found at: {:?}", model_path);
-            panic!("Cannot initialize BlazeFace: model file missing");
-        }
-
-        let blazeface = BlazeFace::new(&model_path)
-            .unwrap_or_else(|e| panic!("Failed to load BlazeFace model: {}", e));
-        Mutex::new(blazeface)
-    })
-}
-
-// Function to detect faces in a frame
-pub fn detect_faces(frame: &Frame) -> Option<Vec<Face>> {
-    let blazeface = get_blazeface();
-    match blazeface.lock() {
-        Ok(mut blazeface) => blazeface.detect_faces(frame),
-        Err(e) => {
-            eprintln!("Failed to acquire mutex lock: {:?}", e);
-            None
-        }
+use visioncore_plugin::{Frame, FrameRef, Face, Landmark, PixelFormat, PluginInterface};
+use facemesh::FaceMesh;
+pub use align::{AlignedChip, AlignmentTemplate, Similarity};
+pub use anchors::{generate_anchors, Anchor, SsdAnchorOptions};
+pub use config::{DetectorConfig, NmsMode, Normalization};
+pub use detector::FaceDetector;
+pub use error::LocinetError;
+pub use facemesh::{FaceLandmarks, NUM_MESH_LANDMARKS};
+pub use headpose::{CameraIntrinsics, HeadPose, HeadPoseConfig, HeadPoseEstimator, PoseSource};
+pub use model::{ModelInfo, TensorSpec};
+pub use recognition::{FaceEmbedder, FaceRecognizer, Gallery, GalleryEntry, Identity, RecognitionConfig};
+pub use smoothing::{FaceSmoother, OneEuroFilter, SmoothedPosition, SmoothingConfig};
+pub use tracker::{FaceTracker, TrackState, TrackedFace, TrackerConfig};
+use std::sync::{Mutex, MutexGuard};
+
+// Pixel formats `detect_faces` reads natively; convert other frames to RGB first
+pub const ACCEPTED_FORMATS: &[PixelFormat] = &[
+    PixelFormat::Rgb,
+    PixelFormat::Bgr,
+    PixelFormat::Rgba,
+    PixelFormat::Bgra,
+    PixelFormat::Nv12,
+    PixelFormat::Yuyv,
+    PixelFormat::Gray,
+];
+
+// Detector behind the free functions below, kept for callers that predate
+// `FaceDetector`. New code should own a `FaceDetector` instead. Loaded on first
+// use; a failed load is reported to the caller and retried on the next call.
+static DETECTOR: Mutex<Option<FaceDetector>> = Mutex::new(None);
+
+// Face mesh behind `detect_landmarks`, loaded on the first landmark request
+static FACEMESH: Mutex<Option<FaceMesh>> = Mutex::new(None);
+
+// A panic in another thread must not disable detection for good
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// Load the shared detector with `config`. Must be called before the first
+// `detect_faces`; without it the default config is used.
+pub fn init(config: DetectorConfig) -> Result<(), LocinetError> {
+    let mut detector = lock(&DETECTOR);
+    if detector.is_some() {
+        return Err(LocinetError::InvalidConfig("Face detector is already initialized".to_string()));
     }
-}
\ No newline at end of file
+    *detector = Some(FaceDetector::new(config)?);
+    Ok(())
+}
+
+fn with_detector<T>(f: impl FnOnce(&mut FaceDetector) -> Result<T, LocinetError>) -> Result<T, LocinetError> {
+    let mut detector = lock(&DETECTOR);
+    let detector = match &mut *detector {
+        Some(detector) => detector,
+        empty => empty.insert(FaceDetector::new(DetectorConfig::default())?),
+    };
+    f(detector)
+}
+
+// Change thresholds, limits or normalization of the shared detector
+pub fn set_detector_config(config: DetectorConfig) -> Result<(), LocinetError> {
+    with_detector(|detector| detector.set_config(config))
+}
+
+// Detect faces in a frame with the shared detector
+pub fn detect_faces(frame: &FrameRef) -> Result<Vec<Face>, LocinetError> {
+    with_detector(|detector| detector.detect_faces(frame))
+}
+
+// Compute the 468-point face mesh of a face detected in `frame`
+pub fn detect_landmarks(frame: &FrameRef, face: &Face) -> Result<FaceLandmarks, LocinetError> {
+    let mut facemesh = lock(&FACEMESH);
+    let facemesh = match &mut *facemesh {
+        Some(facemesh) => facemesh,
+        empty => empty.insert(FaceMesh::new(&facemesh::default_model_path())?),
+    };
+    facemesh.detect_landmarks(frame, face)
+}
+
+// C ABI entry points, exposed to VisionCore through `plugin_interface`
+
+// Hand a boxed slice to the caller; released again by the matching free function.
+// `len` must be null or valid for writes. A null `len` leaves the caller no
+// way to free the items, so nothing is handed out.
+unsafe fn into_raw_parts<T>(items: Vec<T>, len: *mut usize) -> *mut T {
+    if len.is_null() {
+        eprintln!("Result length pointer is null, dropping {} results", items.len());
+        return std::ptr::null_mut();
+    }
+    let items = items.into_boxed_slice();
+    // SAFETY: `len` is not null and the caller guarantees it is writable
+    unsafe { *len = items.len() };
+    Box::into_raw(items) as *mut T
+}
+
+unsafe fn free_raw_parts<T>(ptr: *mut T, len: usize) {
+    if !ptr.is_null() {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)));
+    }
+}
+
+/// # Safety
+/// `frame` must describe a valid buffer and `num_faces` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn locinet_detect_faces(frame: Frame, num_faces: *mut usize) -> *mut Face {
+    let frame = FrameRef::from_raw(&frame);
+    let faces = detect_faces(&frame).unwrap_or_else(|e| {
+        eprintln!("Face detection failed: {}", e);
+        Vec::new()
+    });
+    into_raw_parts(faces, num_faces)
+}
+
+/// # Safety
+/// `faces` and `num_faces` must come from one `locinet_detect_faces` call.
+#[no_mangle]
+pub unsafe extern "C" fn locinet_free_faces(faces: *mut Face, num_faces: usize) {
+    free_raw_parts(faces, num_faces);
+}
+
+// Mesh of every face detected in the frame, NUM_MESH_LANDMARKS points per face
+// in detection order. Faces the mesh model fails on are skipped.
+/// # Safety
+/// `frame` must describe a valid buffer and `num_landmarks` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn locinet_detect_landmarks(frame: Frame, num_landmarks: *mut usize) -> *mut Landmark {
+    let frame = FrameRef::from_raw(&frame);
+    let faces = detect_faces(&frame).unwrap_or_else(|e| {
+        eprintln!("Face detection failed: {}", e);
+        Vec::new()
+    });
+    let landmarks: Vec<Landmark> = faces
+        .iter()
+        .filter_map(|face| match detect_landmarks(&frame, face) {
+            Ok(mesh) => Some(mesh.landmarks),
+            Err(e) => {
+                eprintln!("Landmark detection failed: {}", e);
+                None
+            }
+        })
+        .flatten()
+        .collect();
+    into_raw_parts(landmarks, num_landmarks)
+}
+
+/// # Safety
+/// `landmarks` and `num_landmarks` must come from one `locinet_detect_landmarks` call.
+#[no_mangle]
+pub unsafe extern "C" fn locinet_free_landmarks(landmarks: *mut Landmark, num_landmarks: usize) {
+    free_raw_parts(landmarks, num_landmarks);
+}
+
+pub fn plugin_interface() -> PluginInterface {
+    PluginInterface {
+        detect_landmarks: Some(locinet_detect_landmarks),
+        detect_faces: Some(locinet_detect_faces),
+        free_faces: Some(locinet_free_faces),
+        free_landmarks: Some(locinet_free_landmarks),
+        accepted_formats: ACCEPTED_FORMATS.as_ptr(),
+        num_accepted_formats: ACCEPTED_FORMATS.len(),
+    }
+}
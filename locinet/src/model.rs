@@ -0,0 +1,142 @@
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use tflite::context::ElementKind;
+use tflite::{FlatBufferModel, Interpreter, InterpreterBuilder};
+use tflite::ops::builtin::BuiltinOpResolver;
+
+use crate::error::LocinetError;
+
+pub type TfliteInterpreter = Interpreter<'static, BuiltinOpResolver>;
+
+// Load a TFLite model and allocate its tensors
+pub fn load_interpreter(model_path: &Path) -> Result<TfliteInterpreter, LocinetError> {
+    if !model_path.exists() {
+        return Err(LocinetError::ModelNotFound(model_path.to_path_buf()));
+    }
+
+    let model = FlatBufferModel::build_from_file(model_path)
+        .map_err(|e| LocinetError::ModelInvalid(format!("Failed to load model: {:?}", e)))?;
+    let resolver = BuiltinOpResolver::default();
+    let builder = InterpreterBuilder::new(model, resolver)
+        .map_err(|e| LocinetError::ModelInvalid(format!("Failed to create interpreter builder: {:?}", e)))?;
+    let mut interpreter = builder.build()
+        .map_err(|e| LocinetError::ModelInvalid(format!("Failed to build interpreter: {:?}", e)))?;
+    interpreter.allocate_tensors()
+        .map_err(|e| LocinetError::ModelInvalid(format!("Failed to allocate tensors: {:?}", e)))?;
+    Ok(interpreter)
+}
+
+// Name, element type and dimensions of a model tensor
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TensorSpec {
+    pub name: String,
+    // TFLite type name without its prefix, e.g. "Float32"
+    pub dtype: String,
+    pub shape: Vec<usize>,
+    #[serde(skip)]
+    element_kind: ElementKind,
+}
+
+impl TensorSpec {
+    pub fn read(interpreter: &TfliteInterpreter, index: i32) -> Result<Self, LocinetError> {
+        let info = interpreter.tensor_info(index)
+            .ok_or_else(|| LocinetError::ModelInvalid(format!("Missing tensor {}", index)))?;
+        Ok(TensorSpec {
+            name: info.name,
+            dtype: dtype_name(info.element_kind),
+            shape: info.dims,
+            element_kind: info.element_kind,
+        })
+    }
+
+    // locinet reads and writes every tensor as f32
+    pub fn expect_float32(&self) -> Result<(), LocinetError> {
+        if self.element_kind != ElementKind::kTfLiteFloat32 {
+            return Err(LocinetError::ModelInvalid(format!(
+                "Tensor {} has type {}, expected Float32 (quantized models are not supported)",
+                self.name, self.dtype
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn expect_shape(&self, expected: &[usize]) -> Result<(), LocinetError> {
+        if self.shape != expected {
+            return Err(LocinetError::ShapeMismatch {
+                tensor: self.name.clone(),
+                expected: expected.to_vec(),
+                actual: self.shape.clone(),
+            });
+        }
+        Ok(())
+    }
+}
+
+fn dtype_name(element_kind: ElementKind) -> String {
+    let name = match element_kind {
+        ElementKind::kTfLiteFloat32 => "Float32",
+        ElementKind::kTfLiteFloat16 => "Float16",
+        ElementKind::kTfLiteUInt8 => "UInt8",
+        ElementKind::kTfLiteInt8 => "Int8",
+        ElementKind::kTfLiteInt16 => "Int16",
+        ElementKind::kTfLiteInt32 => "Int32",
+        ElementKind::kTfLiteInt64 => "Int64",
+        // Types no model input or output of ours is expected to have
+        other => return format!("{:?}", other).trim_start_matches("kTfLite").to_string(),
+    };
+    name.to_string()
+}
+
+// What a loaded model looks like, checked against what locinet expects.
+// Serializable so services can log and publish it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ModelInfo {
+    pub path: PathBuf,
+    pub input: TensorSpec,
+    // In the interpreter's output order
+    pub outputs: Vec<TensorSpec>,
+    // Square input size the frames are resampled to
+    pub input_size: usize,
+    // Anchors decoded per frame; None for models without anchors
+    pub num_anchors: Option<usize>,
+}
+
+impl ModelInfo {
+    // Read the tensor layout of a model with one square NHWC RGB float input
+    pub fn read(interpreter: &TfliteInterpreter, path: &Path) -> Result<Self, LocinetError> {
+        if interpreter.inputs().len() != 1 {
+            return Err(LocinetError::ModelInvalid(format!(
+                "Expected one input tensor, found {}", interpreter.inputs().len()
+            )));
+        }
+        let input = TensorSpec::read(interpreter, interpreter.inputs()[0])?;
+        input.expect_float32()?;
+        let dims = &input.shape;
+        if dims.len() != 4 || dims[0] != 1 || dims[1] != dims[2] || dims[3] != 3 {
+            let size = dims.get(1).copied().unwrap_or(0);
+            return Err(LocinetError::ShapeMismatch {
+                tensor: input.name.clone(),
+                expected: vec![1, size, size, 3],
+                actual: dims.clone(),
+            });
+        }
+        let input_size = dims[1];
+
+        let outputs = interpreter.outputs()
+            .iter()
+            .map(|&index| TensorSpec::read(interpreter, index))
+            .collect::<Result<Vec<_>, _>>()?;
+        for output in &outputs {
+            output.expect_float32()?;
+        }
+
+        Ok(ModelInfo { path: path.to_path_buf(), input, outputs, input_size, num_anchors: None })
+    }
+}
+
+// Input tensor index, checked to exist
+pub fn input_index(interpreter: &TfliteInterpreter, n: usize) -> Result<i32, LocinetError> {
+    interpreter.inputs().get(n).copied()
+        .ok_or_else(|| LocinetError::ModelInvalid(format!("Model has no input {}", n)))
+}
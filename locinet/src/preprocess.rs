@@ -0,0 +1,300 @@
+use visioncore_plugin::convert::rgb_at;
+use visioncore_plugin::FrameRef;
+use crate::config::Normalization;
+use crate::error::LocinetError;
+
+// Square region of a frame, rotated by `rotation` radians (clockwise in
+// image coordinates) around its center. Used to crop faces upright.
+#[derive(Debug, Clone, Copy)]
+pub struct RotatedRect {
+    pub center_x: f32,
+    pub center_y: f32,
+    pub size: f32,
+    pub rotation: f32,
+}
+
+impl RotatedRect {
+    // Map a point given in [0, 1] rect coordinates to frame pixels
+    pub fn to_frame(self, u: f32, v: f32) -> [f32; 2] {
+        let dx = (u - 0.5) * self.size;
+        let dy = (v - 0.5) * self.size;
+        let (sin, cos) = self.rotation.sin_cos();
+        [
+            self.center_x + dx * cos - dy * sin,
+            self.center_y + dx * sin + dy * cos,
+        ]
+    }
+
+    // Crop the rect out of `frame` into a square HWC tensor of `size` pixels,
+    // with bilinear sampling and values normalized to [0, 1]. Samples that
+    // fall outside the frame are black.
+    pub fn crop_into(&self, frame: &FrameRef, size: usize, tensor: &mut [f32]) -> Result<(), LocinetError> {
+        check_tensor(tensor, size)?;
+
+        for (v, tensor_row) in tensor.chunks_exact_mut(size * 3).enumerate() {
+            for (u, pixel) in tensor_row.chunks_exact_mut(3).enumerate() {
+                let [x, y] = self.to_frame((u as f32 + 0.5) / size as f32, (v as f32 + 0.5) / size as f32);
+                let rgb = sample_bilinear(frame, x - 0.5, y - 0.5);
+                pixel[0] = rgb[0] / 255.0;
+                pixel[1] = rgb[1] / 255.0;
+                pixel[2] = rgb[2] / 255.0;
+            }
+        }
+        Ok(())
+    }
+}
+
+// A square HWC tensor of `size` pixels holds exactly size * size * 3 values
+pub(crate) fn check_tensor(tensor: &[f32], size: usize) -> Result<(), LocinetError> {
+    if tensor.len() != size * size * 3 {
+        return Err(LocinetError::ShapeMismatch {
+            tensor: "input".to_string(),
+            expected: vec![size, size, 3],
+            actual: vec![tensor.len()],
+        });
+    }
+    Ok(())
+}
+
+// Bilinear sample at (x, y) in pixel-center coordinates; black outside the frame
+pub fn sample_bilinear(frame: &FrameRef, x: f32, y: f32) -> [f32; 3] {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+
+    let pixel = |px: i64, py: i64| -> [f32; 3] {
+        if px < 0 || py < 0 || px >= frame.width as i64 || py >= frame.height as i64 {
+            return [0.0; 3];
+        }
+        rgb_at(frame, px as u32, py as u32).map(|c| c as f32)
+    };
+
+    let (p00, p10) = (pixel(x0, y0), pixel(x0 + 1, y0));
+    let (p01, p11) = (pixel(x0, y0 + 1), pixel(x0 + 1, y0 + 1));
+    let mut out = [0.0; 3];
+    for c in 0..3 {
+        let top = p00[c] + (p10[c] - p00[c]) * fx;
+        let bottom = p01[c] + (p11[c] - p01[c]) * fx;
+        out[c] = top + (bottom - top) * fy;
+    }
+    out
+}
+
+// Geometry of the letterbox: the frame is centered in a max(w, h) square with
+// black padding, which is then scaled to the model input. Maps normalized
+// model coordinates back to frame pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LetterboxTransform {
+    pub frame_w: u32,
+    pub frame_h: u32,
+    // Side of the padded square, in frame pixels
+    pub side: f32,
+    // Padding left of / above the frame, in frame pixels
+    pub offset_x: f32,
+    pub offset_y: f32,
+}
+
+impl LetterboxTransform {
+    pub fn new(width: u32, height: u32) -> Self {
+        let side = width.max(height);
+        Self {
+            frame_w: width,
+            frame_h: height,
+            side: side as f32,
+            offset_x: ((side - width) / 2) as f32,
+            offset_y: ((side - height) / 2) as f32,
+        }
+    }
+
+    // Normalized model [x, y] to frame pixels
+    pub fn to_frame(self, [x, y]: [f32; 2]) -> [f32; 2] {
+        [x * self.side - self.offset_x, y * self.side - self.offset_y]
+    }
+
+    // Normalized [y_min, x_min, y_max, x_max] box to frame pixels, clipped to
+    // the frame so boxes never extend into the padding
+    pub fn bbox_to_frame(self, [y_min, x_min, y_max, x_max]: [f32; 4]) -> [f32; 4] {
+        let [x_min, y_min] = self.to_frame([x_min, y_min]);
+        let [x_max, y_max] = self.to_frame([x_max, y_max]);
+        let (w, h) = (self.frame_w as f32, self.frame_h as f32);
+        [y_min.clamp(0.0, h), x_min.clamp(0.0, w), y_max.clamp(0.0, h), x_max.clamp(0.0, w)]
+    }
+}
+
+// Letterboxes a frame into the square model input (see `LetterboxTransform`),
+// resamples it with nearest-neighbour and normalizes it in a single pass,
+// writing straight into the input tensor.
+pub struct Preprocessor {
+    input_size: usize,
+    normalization: Normalization,
+    // Letterbox of the frame size the lookup tables below were built for
+    transform: LetterboxTransform,
+    // Source column / row for each model column / row, None for padding
+    src_x: Vec<Option<u32>>,
+    src_y: Vec<Option<u32>>,
+}
+
+impl Preprocessor {
+    pub fn new(input_size: usize, normalization: Normalization) -> Self {
+        Self {
+            input_size,
+            normalization,
+            transform: LetterboxTransform::new(0, 0),
+            src_x: Vec::with_capacity(input_size),
+            src_y: Vec::with_capacity(input_size),
+        }
+    }
+
+    // Fill `tensor` (input_size * input_size * 3 floats, HWC) from `frame`.
+    // The frame must have passed `FrameRef::validate`.
+    pub fn run(&mut self, frame: &FrameRef, tensor: &mut [f32]) -> Result<(), LocinetError> {
+        check_tensor(tensor, self.input_size)?;
+        self.update_tables(frame.width, frame.height);
+
+        let normalization = self.normalization;
+        let black = normalization.apply(0);
+        let row_len = self.input_size * 3;
+        for (tensor_row, src_y) in tensor.chunks_exact_mut(row_len).zip(&self.src_y) {
+            let Some(src_y) = *src_y else {
+                tensor_row.fill(black);
+                continue;
+            };
+
+            for (pixel, src_x) in tensor_row.chunks_exact_mut(3).zip(&self.src_x) {
+                match *src_x {
+                    Some(src_x) => {
+                        let rgb = rgb_at(frame, src_x, src_y);
+                        pixel[0] = normalization.apply(rgb[0]);
+                        pixel[1] = normalization.apply(rgb[1]);
+                        pixel[2] = normalization.apply(rgb[2]);
+                    }
+                    None => pixel.fill(black),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_normalization(&mut self, normalization: Normalization) {
+        self.normalization = normalization;
+    }
+
+    // Letterbox used by the last `run`, to map model outputs back to the frame
+    pub fn transform(&self) -> LetterboxTransform {
+        self.transform
+    }
+
+    // Rebuild the sampling tables when the frame size changes
+    fn update_tables(&mut self, width: u32, height: u32) {
+        if (self.transform.frame_w, self.transform.frame_h) == (width, height) {
+            return;
+        }
+        self.transform = LetterboxTransform::new(width, height);
+
+        let target_size = width.max(height);
+        let scale = self.transform.side / self.input_size as f32;
+        let offset_x = self.transform.offset_x as u32;
+        let offset_y = self.transform.offset_y as u32;
+
+        // Nearest-neighbour: sample the padded square at the center of each model pixel
+        let sample = |i: usize, offset: u32, len: u32| -> Option<u32> {
+            let padded = (((i as f32 + 0.5) * scale) as u32).min(target_size - 1);
+            padded.checked_sub(offset).filter(|&p| p < len)
+        };
+
+        self.src_x.clear();
+        self.src_x.extend((0..self.input_size).map(|i| sample(i, offset_x, width)));
+        self.src_y.clear();
+        self.src_y.extend((0..self.input_size).map(|i| sample(i, offset_y, height)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT_SIZE: usize = 128;
+    const SIZES: [(u32, u32); 5] = [(640, 480), (1080, 1920), (128, 128), (300, 97), (1920, 1080)];
+
+    #[test]
+    fn letterbox_round_trip() {
+        for (width, height) in SIZES {
+            let transform = LetterboxTransform::new(width, height);
+            let side = width.max(height) as f32;
+            let (pad_x, pad_y) = ((side - width as f32) / 2.0, (side - height as f32) / 2.0);
+
+            for [x, y] in [[0.0, 0.0], [width as f32, height as f32], [width as f32 * 0.3, height as f32 * 0.8]] {
+                let model = [(x + pad_x.floor()) / side, (y + pad_y.floor()) / side];
+                let [fx, fy] = transform.to_frame(model);
+                assert!((fx - x).abs() < 1e-3 && (fy - y).abs() < 1e-3, "{}x{}: {:?} -> {:?}", width, height, [x, y], [fx, fy]);
+            }
+
+            // The model input's edges map to the padded square around the frame
+            let [x_min, y_min] = transform.to_frame([0.0, 0.0]);
+            let [x_max, y_max] = transform.to_frame([1.0, 1.0]);
+            assert!((x_min + x_max - width as f32).abs() <= 1.0 && (y_min + y_max - height as f32).abs() <= 1.0, "{}x{} is not centered", width, height);
+            assert_eq!((x_max - x_min, y_max - y_min), (side, side));
+        }
+    }
+
+    // A white square drawn into a black frame must map back onto itself after
+    // going through the preprocessor and the letterbox transform
+    #[test]
+    fn preprocessed_square_maps_back_to_frame() {
+        for (width, height) in SIZES {
+            let block = width.max(height) / 8;
+            let (left, top) = (width / 5, height - block - height / 10);
+            let mut data = vec![0u8; (width * height * 3) as usize];
+            for y in top..top + block {
+                for x in left..left + block {
+                    let i = ((y * width + x) * 3) as usize;
+                    data[i..i + 3].fill(255);
+                }
+            }
+            let frame = FrameRef::rgb(&data, width, height);
+
+            let mut preprocessor = Preprocessor::new(INPUT_SIZE, Normalization::Unit);
+            let mut tensor = vec![0.0; INPUT_SIZE * INPUT_SIZE * 3];
+            preprocessor.run(&frame, &mut tensor).unwrap();
+
+            let (mut sum_u, mut sum_v, mut count) = (0.0, 0.0, 0.0);
+            for (i, pixel) in tensor.chunks_exact(3).enumerate() {
+                if pixel[0] > 0.5 {
+                    sum_u += (i % INPUT_SIZE) as f32 + 0.5;
+                    sum_v += (i / INPUT_SIZE) as f32 + 0.5;
+                    count += 1.0;
+                }
+            }
+            assert!(count > 0.0, "{}x{}: square lost in preprocessing", width, height);
+
+            let center = [sum_u / count / INPUT_SIZE as f32, sum_v / count / INPUT_SIZE as f32];
+            let [x, y] = preprocessor.transform().to_frame(center);
+            let expected = [left as f32 + block as f32 / 2.0, top as f32 + block as f32 / 2.0];
+            // One model pixel of tolerance
+            let tolerance = width.max(height) as f32 / INPUT_SIZE as f32;
+            assert!(
+                (x - expected[0]).abs() <= tolerance && (y - expected[1]).abs() <= tolerance,
+                "{}x{}: square at {:?} mapped back to {:?}", width, height, expected, [x, y]
+            );
+        }
+    }
+
+    #[test]
+    fn bbox_is_clipped_to_frame() {
+        let transform = LetterboxTransform::new(1080, 1920);
+        let [y_min, x_min, y_max, x_max] = transform.bbox_to_frame([0.0, 0.0, 1.0, 1.0]);
+        assert_eq!([y_min, x_min, y_max, x_max], [0.0, 0.0, 1920.0, 1080.0]);
+    }
+
+    #[test]
+    fn wrong_tensor_size_is_an_error() {
+        let data = vec![0; 64 * 48 * 3];
+        let frame = FrameRef::rgb(&data, 64, 48);
+        let mut tensor = vec![0.0; INPUT_SIZE * INPUT_SIZE];
+
+        let mut preprocessor = Preprocessor::new(INPUT_SIZE, Normalization::Unit);
+        assert!(matches!(preprocessor.run(&frame, &mut tensor), Err(LocinetError::ShapeMismatch { .. })));
+        let roi = RotatedRect { center_x: 32.0, center_y: 24.0, size: 40.0, rotation: 0.0 };
+        assert!(matches!(roi.crop_into(&frame, INPUT_SIZE, &mut tensor), Err(LocinetError::ShapeMismatch { .. })));
+    }
+}
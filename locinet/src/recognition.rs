@@ -0,0 +1,437 @@
+// Face recognition: an embedding model (e.g. MobileFaceNet) turns an aligned
+// face chip into a unit vector, which is matched by cosine similarity against
+// a gallery of enrolled users kept in a JSON file.
+
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use visioncore_plugin::{Face, FrameRef};
+
+use crate::align::AlignmentTemplate;
+use crate::config::Normalization;
+use crate::error::LocinetError;
+use crate::model::{input_index, load_interpreter, ModelInfo, TfliteInterpreter};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecognitionConfig {
+    pub model_path: PathBuf,
+    // JSON file of enrolled users; created on the first enrollment
+    pub gallery_path: PathBuf,
+    // Minimum cosine similarity to report a known identity; below it the face
+    // is unknown. Raise it for fewer false matches.
+    pub similarity_threshold: f32,
+    // Input range of the embedding model; MobileFaceNet and ArcFace use [-1, 1]
+    pub normalization: Normalization,
+    // A tracked face is identified when its track starts and again after
+    // this many frames, rather than on every frame
+    pub refresh_frames: u32,
+}
+
+impl Default for RecognitionConfig {
+    fn default() -> Self {
+        RecognitionConfig {
+            model_path: default_model_path(),
+            gallery_path: PathBuf::from("locinet/gallery.json"),
+            similarity_threshold: 0.5,
+            normalization: Normalization::Symmetric,
+            refresh_frames: 30,
+        }
+    }
+}
+
+// LOCINET_EMBEDDING_MODEL_PATH overrides the default embedding model location
+fn default_model_path() -> PathBuf {
+    env::var("LOCINET_EMBEDDING_MODEL_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("locinet/models/face_embedding.tflite"))
+}
+
+impl RecognitionConfig {
+    pub fn validate(&self) -> Result<(), LocinetError> {
+        if !(-1.0..=1.0).contains(&self.similarity_threshold) {
+            return Err(LocinetError::InvalidConfig(format!(
+                "similarity_threshold must be in [-1, 1], got {}", self.similarity_threshold
+            )));
+        }
+        if self.refresh_frames == 0 {
+            return Err(LocinetError::InvalidConfig("refresh_frames must be at least 1".to_string()));
+        }
+        Ok(())
+    }
+}
+
+// Who a face belongs to, as published with it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Identity {
+    // Best-matching enrolled user, None when no one reaches the threshold
+    pub identity: Option<String>,
+    // Cosine similarity to the best match, whether or not it passed the
+    // threshold; 0 with an empty gallery
+    pub similarity: f32,
+}
+
+// Runs the embedding model on aligned face chips
+pub struct FaceEmbedder {
+    interpreter: TfliteInterpreter,
+    info: ModelInfo,
+    // ArcFace template scaled to the model input
+    template: AlignmentTemplate,
+    normalization: Normalization,
+}
+
+impl FaceEmbedder {
+    pub fn new(model_path: &Path, normalization: Normalization) -> Result<Self, LocinetError> {
+        let interpreter = load_interpreter(model_path)?;
+
+        // Square NHWC RGB input (112x112 for MobileFaceNet) and one embedding output
+        let info = ModelInfo::read(&interpreter, model_path)?;
+        match info.outputs.first() {
+            Some(output) if output.shape.iter().product::<usize>() > 1 => {}
+            _ => {
+                return Err(LocinetError::ModelInvalid(
+                    "Embedding model needs an embedding vector as its first output".to_string(),
+                ))
+            }
+        }
+
+        let template = AlignmentTemplate::arcface().with_size(info.input_size);
+        Ok(FaceEmbedder { interpreter, info, template, normalization })
+    }
+
+    pub fn model_info(&self) -> &ModelInfo {
+        &self.info
+    }
+
+    pub fn embedding_size(&self) -> usize {
+        self.info.outputs[0].shape.iter().product()
+    }
+
+    // Unit-length embedding of a face detected in `frame`
+    pub fn embed(&mut self, frame: &FrameRef, face: &Face) -> Result<Vec<f32>, LocinetError> {
+        frame.validate().map_err(LocinetError::InvalidFrame)?;
+
+        let input_index = input_index(&self.interpreter, 0)?;
+        let input_tensor_data = self.interpreter.tensor_data_mut(input_index)
+            .map_err(|e| LocinetError::InferenceFailed(format!("Failed to get input tensor data: {:?}", e)))?;
+        if self.template.warp_into(frame, face, self.normalization, input_tensor_data)?.is_none() {
+            return Err(LocinetError::InferenceFailed("Face keypoints are degenerate, cannot align the face".to_string()));
+        }
+
+        self.interpreter.invoke()
+            .map_err(|e| LocinetError::InferenceFailed(format!("{:?}", e)))?;
+
+        let output_index = self.interpreter.outputs()[0];
+        let output: &[f32] = self.interpreter.tensor_data(output_index)
+            .map_err(|e| LocinetError::InferenceFailed(format!("Failed to get embedding: {:?}", e)))?;
+        let norm = output.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if norm <= f32::EPSILON {
+            return Err(LocinetError::InferenceFailed("Embedding is all zeros".to_string()));
+        }
+        Ok(output.iter().map(|v| v / norm).collect())
+    }
+}
+
+// One enrolled user with all their enrollment samples
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GalleryEntry {
+    pub name: String,
+    pub embeddings: Vec<Vec<f32>>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct GalleryFile {
+    identities: Vec<GalleryEntry>,
+}
+
+// Enrolled users, kept in sync with a JSON file. Changes are written to the
+// file immediately; other processes' changes are picked up by `reload_if_changed`.
+pub struct Gallery {
+    path: PathBuf,
+    entries: Vec<GalleryEntry>,
+    // Modification time of the file when it was last read or written
+    modified: Option<SystemTime>,
+}
+
+impl Gallery {
+    // Load the gallery at `path`; a missing file is an empty gallery
+    pub fn open(path: &Path) -> Result<Self, LocinetError> {
+        let mut gallery = Gallery { path: path.to_path_buf(), entries: Vec::new(), modified: None };
+        gallery.load()?;
+        Ok(gallery)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn entries(&self) -> &[GalleryEntry] {
+        &self.entries
+    }
+
+    // Enrolled names with their number of samples
+    pub fn list(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.entries.iter().map(|entry| (entry.name.as_str(), entry.embeddings.len()))
+    }
+
+    // Add an enrollment sample for `name`, creating the user if needed. More
+    // samples (different angles, lighting) make matching more reliable.
+    pub fn add(&mut self, name: &str, embedding: Vec<f32>) -> Result<(), LocinetError> {
+        if name.trim().is_empty() {
+            return Err(LocinetError::Gallery("Identity name must not be empty".to_string()));
+        }
+        match self.entries.iter_mut().find(|entry| entry.name == name) {
+            Some(entry) => entry.embeddings.push(embedding),
+            None => self.entries.push(GalleryEntry { name: name.to_string(), embeddings: vec![embedding] }),
+        }
+        self.save()
+    }
+
+    // Remove a user and all their samples. Returns false if `name` was not enrolled.
+    pub fn remove(&mut self, name: &str) -> Result<bool, LocinetError> {
+        let len = self.entries.len();
+        self.entries.retain(|entry| entry.name != name);
+        if self.entries.len() == len {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    // Best-matching user by cosine similarity of unit embeddings. Samples of a
+    // different size (from another embedding model) are ignored.
+    pub fn best_match(&self, embedding: &[f32]) -> Option<(&str, f32)> {
+        self.entries
+            .iter()
+            .flat_map(|entry| entry.embeddings.iter().map(move |sample| (entry.name.as_str(), sample)))
+            .filter(|(_, sample)| sample.len() == embedding.len())
+            .map(|(name, sample)| (name, sample.iter().zip(embedding).map(|(a, b)| a * b).sum::<f32>()))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+    }
+
+    // Best match for `embedding`, reported as unknown below `threshold`
+    pub fn identify(&self, embedding: &[f32], threshold: f32) -> Identity {
+        match self.best_match(embedding) {
+            Some((name, similarity)) => Identity {
+                identity: (similarity >= threshold).then(|| name.to_string()),
+                similarity,
+            },
+            None => Identity { identity: None, similarity: 0.0 },
+        }
+    }
+
+    // Re-read the file if it changed on disk since it was last read or written
+    pub fn reload_if_changed(&mut self) -> Result<bool, LocinetError> {
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        if modified == self.modified {
+            return Ok(false);
+        }
+        self.load()?;
+        Ok(true)
+    }
+
+    fn load(&mut self) -> Result<(), LocinetError> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                self.entries.clear();
+                self.modified = None;
+                return Ok(());
+            }
+            Err(e) => return Err(LocinetError::Gallery(format!("Failed to read {:?}: {}", self.path, e))),
+        };
+        let file: GalleryFile = serde_json::from_str(&contents)
+            .map_err(|e| LocinetError::Gallery(format!("Failed to parse {:?}: {}", self.path, e)))?;
+        self.entries = file.identities;
+        self.modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        Ok(())
+    }
+
+    // Write to a temporary file and rename it over the gallery, so a reader
+    // never sees a half-written file
+    fn save(&mut self) -> Result<(), LocinetError> {
+        let file = GalleryFile { identities: self.entries.clone() };
+        let contents = serde_json::to_string_pretty(&file)
+            .map_err(|e| LocinetError::Gallery(format!("Failed to serialize gallery: {}", e)))?;
+
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .map_err(|e| LocinetError::Gallery(format!("Failed to create {:?}: {}", dir, e)))?;
+        }
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, contents)
+            .and_then(|_| fs::rename(&tmp_path, &self.path))
+            .map_err(|e| LocinetError::Gallery(format!("Failed to write {:?}: {}", self.path, e)))?;
+        self.modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        Ok(())
+    }
+}
+
+// Embedding model plus gallery: identifies faces and enrolls new users
+pub struct FaceRecognizer {
+    config: RecognitionConfig,
+    embedder: FaceEmbedder,
+    gallery: Gallery,
+}
+
+impl FaceRecognizer {
+    pub fn new(config: RecognitionConfig) -> Result<Self, LocinetError> {
+        config.validate()?;
+        let embedder = FaceEmbedder::new(&config.model_path, config.normalization)?;
+        let gallery = Gallery::open(&config.gallery_path)?;
+        Ok(FaceRecognizer { config, embedder, gallery })
+    }
+
+    pub fn config(&self) -> &RecognitionConfig {
+        &self.config
+    }
+
+    pub fn model_info(&self) -> &ModelInfo {
+        self.embedder.model_info()
+    }
+
+    pub fn gallery(&self) -> &Gallery {
+        &self.gallery
+    }
+
+    pub fn gallery_mut(&mut self) -> &mut Gallery {
+        &mut self.gallery
+    }
+
+    // Match a face detected in `frame` against the gallery
+    pub fn identify(&mut self, frame: &FrameRef, face: &Face) -> Result<Identity, LocinetError> {
+        let embedding = self.embedder.embed(frame, face)?;
+        Ok(self.gallery.identify(&embedding, self.config.similarity_threshold))
+    }
+
+    // Add a face detected in `frame` to the gallery as a sample of `name`
+    pub fn enroll(&mut self, name: &str, frame: &FrameRef, face: &Face) -> Result<(), LocinetError> {
+        let embedding = self.embedder.embed(frame, face)?;
+        self.gallery.add(name, embedding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::process;
+    use std::time::Duration;
+
+    // Empty directory for one test, removed again when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = env::temp_dir().join(format!("locinet-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn names(gallery: &Gallery) -> Vec<(&str, usize)> {
+        gallery.list().collect()
+    }
+
+    #[test]
+    fn add_remove_and_list() {
+        let dir = TempDir::new("gallery-edit");
+        let mut gallery = Gallery::open(&dir.0.join("gallery.json")).unwrap();
+        assert!(names(&gallery).is_empty());
+
+        gallery.add("alice", vec![1.0, 0.0]).unwrap();
+        gallery.add("bob", vec![0.0, 1.0]).unwrap();
+        gallery.add("alice", vec![0.6, 0.8]).unwrap();
+        assert_eq!(names(&gallery), [("alice", 2), ("bob", 1)]);
+        assert!(gallery.add(" ", vec![1.0, 0.0]).is_err());
+
+        assert!(gallery.remove("alice").unwrap());
+        assert!(!gallery.remove("carol").unwrap());
+        assert_eq!(names(&gallery), [("bob", 1)]);
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let dir = TempDir::new("gallery-round-trip");
+        // The file and its directory are created on the first enrollment
+        let path = dir.0.join("users").join("gallery.json");
+        let mut gallery = Gallery::open(&path).unwrap();
+        gallery.add("alice", vec![1.0, 0.0]).unwrap();
+        gallery.add("alice", vec![0.6, 0.8]).unwrap();
+        gallery.add("bob", vec![0.0, 1.0]).unwrap();
+
+        let loaded = Gallery::open(&path).unwrap();
+        assert_eq!(loaded.entries(), gallery.entries());
+        assert!(!path.with_extension("json.tmp").exists());
+    }
+
+    #[test]
+    fn reloads_after_external_write() {
+        let dir = TempDir::new("gallery-reload");
+        let path = dir.0.join("gallery.json");
+        let mut gallery = Gallery::open(&path).unwrap();
+        gallery.add("alice", vec![1.0, 0.0]).unwrap();
+        assert!(!gallery.reload_if_changed().unwrap());
+
+        // Another process enrolls bob
+        let mut other = Gallery::open(&path).unwrap();
+        other.add("bob", vec![0.0, 1.0]).unwrap();
+        // Make sure the change shows even where file times are coarse
+        let modified = fs::metadata(&path).unwrap().modified().unwrap() + Duration::from_secs(2);
+        File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+
+        assert!(gallery.reload_if_changed().unwrap());
+        assert_eq!(names(&gallery), [("alice", 1), ("bob", 1)]);
+        assert!(!gallery.reload_if_changed().unwrap());
+
+        // A deleted file empties the gallery
+        fs::remove_file(&path).unwrap();
+        assert!(gallery.reload_if_changed().unwrap());
+        assert!(names(&gallery).is_empty());
+    }
+
+    #[test]
+    fn best_match_and_threshold() {
+        let dir = TempDir::new("gallery-match");
+        let mut gallery = Gallery::open(&dir.0.join("gallery.json")).unwrap();
+        assert_eq!(gallery.best_match(&[1.0, 0.0]), None);
+        assert_eq!(gallery.identify(&[1.0, 0.0], 0.5), Identity { identity: None, similarity: 0.0 });
+
+        gallery.add("alice", vec![1.0, 0.0]).unwrap();
+        gallery.add("alice", vec![0.6, 0.8]).unwrap();
+        gallery.add("bob", vec![0.0, 1.0]).unwrap();
+        // A sample from a model with another embedding size is never matched
+        gallery.add("carol", vec![0.0, 0.6, 0.8]).unwrap();
+
+        // The best sample counts: alice's second one is closer than bob
+        let (name, similarity) = gallery.best_match(&[0.0, 1.0]).unwrap();
+        assert_eq!(name, "bob");
+        assert!((similarity - 1.0).abs() < 1e-6);
+        let (name, similarity) = gallery.best_match(&[0.8, 0.6]).unwrap();
+        assert_eq!(name, "alice");
+        assert!((similarity - 0.96).abs() < 1e-6);
+
+        // Only carol has three-value samples
+        let (name, _) = gallery.best_match(&[0.0, 0.0, 1.0]).unwrap();
+        assert_eq!(name, "carol");
+        assert_eq!(gallery.best_match(&[1.0]), None);
+
+        // Below the threshold the face is unknown, but the similarity is kept
+        let identity = gallery.identify(&[-0.6, 0.8], 0.9);
+        assert_eq!(identity.identity, None);
+        assert!((identity.similarity - 0.8).abs() < 1e-6);
+        let identity = gallery.identify(&[-0.6, 0.8], 0.5);
+        assert_eq!(identity.identity.as_deref(), Some("bob"));
+    }
+}
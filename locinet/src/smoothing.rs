@@ -0,0 +1,198 @@
+// One Euro filter (Casiez et al., 2012): a low-pass filter whose cutoff rises
+// with speed, so a still face is smoothed heavily while a moving one is
+// followed with little lag.
+
+use serde::{Deserialize, Serialize};
+use visioncore_plugin::Face;
+
+use crate::error::LocinetError;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SmoothingConfig {
+    // Cutoff frequency in Hz at rest; lower removes more jitter
+    pub min_cutoff: f32,
+    // Cutoff increase per pixel/second of speed; higher reduces lag on fast motion
+    pub beta: f32,
+    // Cutoff frequency in Hz of the velocity estimate
+    pub d_cutoff: f32,
+    // Start over from the next sample after a gap this long, rather than
+    // pulling it toward a position that is long out of date
+    pub reset_after_ms: u64,
+}
+
+impl Default for SmoothingConfig {
+    fn default() -> Self {
+        SmoothingConfig {
+            min_cutoff: 1.0,
+            beta: 0.05,
+            d_cutoff: 1.0,
+            reset_after_ms: 1000,
+        }
+    }
+}
+
+impl SmoothingConfig {
+    pub fn validate(&self) -> Result<(), LocinetError> {
+        let positive = |value: f32| value.is_finite() && value > 0.0;
+        if !(positive(self.min_cutoff) && positive(self.d_cutoff) && self.beta.is_finite() && self.beta >= 0.0) {
+            return Err(LocinetError::InvalidConfig(
+                "smoothing cutoffs must be positive and beta must not be negative".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OneEuroFilter {
+    // Filtered value and velocity (units per second), None before the first sample
+    state: Option<(f32, f32)>,
+}
+
+impl OneEuroFilter {
+    // Filter `value` observed `dt` seconds after the previous one
+    pub fn filter(&mut self, value: f32, dt: f32, config: &SmoothingConfig) -> f32 {
+        let gap = dt * 1000.0 > config.reset_after_ms as f32;
+        let (previous, previous_velocity) = match self.state {
+            Some(state) if dt > 0.0 && !gap => state,
+            // No time has passed, so there is no new information
+            Some((previous, _)) if !gap => return previous,
+            // First sample, or the first after a gap: nothing to smooth against yet
+            _ => {
+                self.state = Some((value, 0.0));
+                return value;
+            }
+        };
+
+        let velocity = lerp(previous_velocity, (value - previous) / dt, alpha(config.d_cutoff, dt));
+        let cutoff = config.min_cutoff + config.beta * velocity.abs();
+        let filtered = lerp(previous, value, alpha(cutoff, dt));
+        self.state = Some((filtered, velocity));
+        filtered
+    }
+
+    // Filtered velocity in units per second
+    pub fn velocity(&self) -> f32 {
+        self.state.map_or(0.0, |(_, velocity)| velocity)
+    }
+}
+
+fn alpha(cutoff: f32, dt: f32) -> f32 {
+    let tau = 1.0 / (2.0 * std::f32::consts::PI * cutoff);
+    1.0 / (1.0 + tau / dt)
+}
+
+fn lerp(from: f32, to: f32, t: f32) -> f32 {
+    from + (to - from) * t
+}
+
+// Filtered box and center of one tracked face
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct SmoothedPosition {
+    // [x, y, w, h] in frame pixels, like `Face::bbox`
+    pub bbox: [f32; 4],
+    // [y, x] in frame pixels, like `Face::center`
+    pub center: [f32; 2],
+    // Center velocity [vx, vy] in pixels per second
+    pub velocity: [f32; 2],
+}
+
+// One Euro filters for the box and center of one track
+#[derive(Debug, Clone, Default)]
+pub struct FaceSmoother {
+    bbox: [OneEuroFilter; 4],
+    center_x: OneEuroFilter,
+    center_y: OneEuroFilter,
+}
+
+impl FaceSmoother {
+    pub fn filter(&mut self, face: &Face, dt: f32, config: &SmoothingConfig) -> SmoothedPosition {
+        let mut bbox = face.bbox;
+        for (value, filter) in bbox.iter_mut().zip(&mut self.bbox) {
+            *value = filter.filter(*value, dt, config);
+        }
+        let [y, x] = face.center;
+        let x = self.center_x.filter(x, dt, config);
+        let y = self.center_y.filter(y, dt, config);
+
+        SmoothedPosition {
+            bbox,
+            center: [y, x],
+            velocity: [self.center_x.velocity(), self.center_y.velocity()],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    const DT: f32 = 1.0 / 30.0;
+
+    #[test]
+    fn constant_input_is_unchanged() {
+        let config = SmoothingConfig::default();
+        let mut filter = OneEuroFilter::default();
+        for _ in 0..100 {
+            assert_eq!(filter.filter(42.5, DT, &config), 42.5);
+        }
+        assert_eq!(filter.velocity(), 0.0);
+    }
+
+    #[test]
+    fn alpha_for_known_cutoffs() {
+        // A cutoff of 1 / 2pi Hz has a time constant of 1 s
+        assert!((alpha(1.0 / (2.0 * PI), 1.0) - 0.5).abs() < 1e-6);
+        assert!((alpha(1.0 / (2.0 * PI), 3.0) - 0.75).abs() < 1e-6);
+        // 1 Hz at 30 fps: 1 / (1 + 30 / 2pi)
+        assert!((alpha(1.0, DT) - 0.173_173).abs() < 1e-5);
+        // Higher cutoffs follow the input more closely
+        assert!(alpha(10.0, DT) > alpha(1.0, DT));
+    }
+
+    #[test]
+    fn step_moves_by_alpha() {
+        // Without beta the cutoff stays at min_cutoff, so a step is followed by alpha
+        let config = SmoothingConfig { min_cutoff: 1.0 / (2.0 * PI), beta: 0.0, ..SmoothingConfig::default() };
+        let mut filter = OneEuroFilter::default();
+        assert_eq!(filter.filter(0.0, 0.0, &config), 0.0);
+        assert!((filter.filter(10.0, 0.5, &config) - 10.0 / 3.0).abs() < 1e-5);
+
+        // No time passed: the previous value is kept
+        assert!((filter.filter(100.0, 0.0, &config) - 10.0 / 3.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn gap_resets_filter() {
+        let config = SmoothingConfig::default();
+        let mut filter = OneEuroFilter::default();
+        for i in 0..10 {
+            filter.filter(i as f32 * 10.0, DT, &config);
+        }
+        assert!(filter.velocity() > 0.0);
+
+        // Within the limit the new sample is smoothed
+        assert!(filter.filter(500.0, 0.5, &config) < 500.0);
+
+        // After a longer gap it is taken as is and the velocity starts over
+        assert_eq!(filter.filter(0.0, 1.5, &config), 0.0);
+        assert_eq!(filter.velocity(), 0.0);
+        assert_eq!(filter.filter(0.0, DT, &config), 0.0);
+    }
+
+    #[test]
+    fn rejects_invalid_configs() {
+        assert!(SmoothingConfig::default().validate().is_ok());
+        for config in [
+            SmoothingConfig { min_cutoff: 0.0, ..SmoothingConfig::default() },
+            SmoothingConfig { min_cutoff: f32::NAN, ..SmoothingConfig::default() },
+            SmoothingConfig { d_cutoff: f32::INFINITY, ..SmoothingConfig::default() },
+            SmoothingConfig { beta: -0.1, ..SmoothingConfig::default() },
+            SmoothingConfig { beta: f32::NAN, ..SmoothingConfig::default() },
+        ] {
+            assert!(config.validate().is_err(), "{:?} was accepted", config);
+        }
+    }
+}
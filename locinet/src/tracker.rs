@@ -0,0 +1,582 @@
+// SORT-style multi-face tracker: each track predicts its box with a
+// constant-velocity Kalman filter, predictions are matched to new detections
+// by IoU with the Hungarian algorithm, and tracks are born and retired with
+// hysteresis so a single missed or spurious detection does not change IDs.
+
+use serde::{Deserialize, Serialize};
+use visioncore_plugin::Face;
+
+use crate::error::LocinetError;
+use crate::smoothing::{FaceSmoother, SmoothedPosition, SmoothingConfig};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrackerConfig {
+    // A detection must overlap a track's predicted box by at least this IoU to continue it
+    pub iou_threshold: f32,
+    // Consecutive matched frames before a new track is confirmed
+    pub min_hits: u32,
+    // Frames a confirmed track survives without detections (reported as lost)
+    pub max_age: u32,
+    // Standard deviation of detector noise, as a fraction of the face size
+    pub measurement_noise: f32,
+    // Standard deviation of face acceleration, in face sizes per second squared
+    pub process_noise: f32,
+    // Drop all tracks when no frame arrived for this long, e.g. after a camera outage
+    pub reset_after_ms: u64,
+    // One Euro filter applied to each track's box and center
+    pub smoothing: SmoothingConfig,
+}
+
+impl Default for TrackerConfig {
+    fn default() -> Self {
+        TrackerConfig {
+            iou_threshold: 0.3,
+            min_hits: 3,
+            max_age: 10,
+            measurement_noise: 0.05,
+            process_noise: 2.0,
+            reset_after_ms: 1000,
+            smoothing: SmoothingConfig::default(),
+        }
+    }
+}
+
+impl TrackerConfig {
+    pub fn validate(&self) -> Result<(), LocinetError> {
+        if !(0.0..=1.0).contains(&self.iou_threshold) {
+            return Err(LocinetError::InvalidConfig(format!("tracker iou_threshold must be in [0, 1], got {}", self.iou_threshold)));
+        }
+        if self.measurement_noise <= 0.0 || self.process_noise <= 0.0 {
+            return Err(LocinetError::InvalidConfig("tracker noise levels must be positive".to_string()));
+        }
+        self.smoothing.validate()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrackState {
+    // Seen in fewer than `min_hits` consecutive frames; may be a false positive
+    Tentative,
+    // Matched to a detection in this frame
+    Confirmed,
+    // Not detected in this frame (occluded or out of view); the face is the prediction
+    Lost,
+}
+
+// A face with its track identity
+#[derive(Debug, Clone, Serialize)]
+pub struct TrackedFace {
+    pub track_id: u64,
+    pub state: TrackState,
+    // Frames since the track was created
+    pub age: u32,
+    // Frames since the face was last detected; 0 unless lost
+    pub frames_since_seen: u32,
+    // Jitter-free box and center with velocity; `face` keeps the raw values
+    pub smoothed: SmoothedPosition,
+    #[serde(flatten)]
+    pub face: Face,
+}
+
+// Constant-velocity Kalman filter on one box coordinate
+#[derive(Debug, Clone, Copy)]
+struct Kalman1D {
+    position: f32,
+    velocity: f32,
+    // Covariance of (position, velocity)
+    p: [[f32; 2]; 2],
+}
+
+impl Kalman1D {
+    fn new(position: f32, position_var: f32, velocity_var: f32) -> Self {
+        Kalman1D {
+            position,
+            velocity: 0.0,
+            p: [[position_var, 0.0], [0.0, velocity_var]],
+        }
+    }
+
+    // `accel_var` is the variance of the (white noise) acceleration
+    fn predict(&mut self, dt: f32, accel_var: f32) {
+        self.position += self.velocity * dt;
+        let [[p00, p01], [p10, p11]] = self.p;
+        let (dt2, dt3) = (dt * dt, dt * dt * dt);
+        self.p = [
+            [
+                p00 + dt * (p10 + p01) + dt2 * p11 + accel_var * dt3 / 3.0,
+                p01 + dt * p11 + accel_var * dt2 / 2.0,
+            ],
+            [
+                p10 + dt * p11 + accel_var * dt2 / 2.0,
+                p11 + accel_var * dt,
+            ],
+        ];
+    }
+
+    fn update(&mut self, measurement: f32, measurement_var: f32) {
+        let [[p00, p01], [p10, p11]] = self.p;
+        let s = p00 + measurement_var;
+        let (k0, k1) = (p00 / s, p10 / s);
+        let residual = measurement - self.position;
+        self.position += k0 * residual;
+        self.velocity += k1 * residual;
+        self.p = [
+            [(1.0 - k0) * p00, (1.0 - k0) * p01],
+            [p10 - k1 * p00, p11 - k1 * p01],
+        ];
+    }
+}
+
+struct Track {
+    id: u64,
+    // Filters on the box center and size: cx, cy, w, h
+    filters: [Kalman1D; 4],
+    // Last matched detection; reported with the predicted box while lost
+    face: Face,
+    smoother: FaceSmoother,
+    confirmed: bool,
+    age: u32,
+    hits: u32,
+    frames_since_seen: u32,
+}
+
+impl Track {
+    fn new(id: u64, face: Face, config: &TrackerConfig) -> Self {
+        let size = face_size(&face);
+        let position_var = (config.measurement_noise * size).powi(2);
+        // Unknown initial velocity: allow about one face size per second
+        let velocity_var = size * size;
+        let filters = box_state(&face).map(|value| Kalman1D::new(value, position_var, velocity_var));
+        let confirmed = config.min_hits <= 1;
+        Track {
+            id,
+            filters,
+            face,
+            smoother: FaceSmoother::default(),
+            confirmed,
+            age: 0,
+            hits: 1,
+            frames_since_seen: 0,
+        }
+    }
+
+    fn predict(&mut self, dt: f32, config: &TrackerConfig) {
+        let size = self.size();
+        let accel_var = (config.process_noise * size).powi(2);
+        for filter in &mut self.filters {
+            filter.predict(dt, accel_var);
+        }
+        self.age += 1;
+        self.frames_since_seen += 1;
+    }
+
+    fn update(&mut self, face: Face, config: &TrackerConfig) {
+        let measurement_var = (config.measurement_noise * face_size(&face)).powi(2);
+        for (filter, value) in self.filters.iter_mut().zip(box_state(&face)) {
+            filter.update(value, measurement_var);
+        }
+        self.face = face;
+        self.hits += 1;
+        self.frames_since_seen = 0;
+        if self.hits >= config.min_hits {
+            self.confirmed = true;
+        }
+    }
+
+    fn size(&self) -> f32 {
+        self.filters[2].position.max(self.filters[3].position).max(1.0)
+    }
+
+    // Predicted [x, y, w, h] box
+    fn predicted_bbox(&self) -> [f32; 4] {
+        let [cx, cy, w, h] = self.filters.map(|f| f.position);
+        let (w, h) = (w.max(1.0), h.max(1.0));
+        [cx - w / 2.0, cy - h / 2.0, w, h]
+    }
+
+    fn state(&self) -> TrackState {
+        if self.frames_since_seen > 0 {
+            TrackState::Lost
+        } else if self.confirmed {
+            TrackState::Confirmed
+        } else {
+            TrackState::Tentative
+        }
+    }
+
+    // The last detection, moved to the predicted box while the face is lost,
+    // and its smoothed position `dt` seconds after the previous frame
+    fn tracked_face(&mut self, dt: f32, smoothing: &SmoothingConfig) -> TrackedFace {
+        let mut face = self.face;
+        if self.frames_since_seen > 0 {
+            let bbox = self.predicted_bbox();
+            let (dx, dy) = (
+                bbox[0] + bbox[2] / 2.0 - (face.bbox[0] + face.bbox[2] / 2.0),
+                bbox[1] + bbox[3] / 2.0 - (face.bbox[1] + face.bbox[3] / 2.0),
+            );
+            face.bbox = bbox;
+            face.center = [face.center[0] + dy, face.center[1] + dx];
+            for keypoint in &mut face.keypoints {
+                keypoint[0] += dx;
+                keypoint[1] += dy;
+            }
+        }
+        TrackedFace {
+            track_id: self.id,
+            state: self.state(),
+            age: self.age,
+            frames_since_seen: self.frames_since_seen,
+            smoothed: self.smoother.filter(&face, dt, smoothing),
+            face,
+        }
+    }
+}
+
+// [cx, cy, w, h] of a face box
+fn box_state(face: &Face) -> [f32; 4] {
+    let [x, y, w, h] = face.bbox;
+    [x + w / 2.0, y + h / 2.0, w, h]
+}
+
+fn face_size(face: &Face) -> f32 {
+    face.bbox[2].max(face.bbox[3]).max(1.0)
+}
+
+// IoU of two [x, y, w, h] boxes
+fn iou(a: [f32; 4], b: [f32; 4]) -> f32 {
+    let x_min = a[0].max(b[0]);
+    let y_min = a[1].max(b[1]);
+    let x_max = (a[0] + a[2]).min(b[0] + b[2]);
+    let y_max = (a[1] + a[3]).min(b[1] + b[3]);
+    if x_max <= x_min || y_max <= y_min {
+        return 0.0;
+    }
+    let intersection = (x_max - x_min) * (y_max - y_min);
+    intersection / (a[2] * a[3] + b[2] * b[3] - intersection)
+}
+
+// Tracks the faces of one camera. Feed it every frame's detections in
+// capture order, including frames without faces.
+pub struct FaceTracker {
+    config: TrackerConfig,
+    tracks: Vec<Track>,
+    next_id: u64,
+    last_timestamp_ns: Option<u64>,
+}
+
+impl FaceTracker {
+    pub fn new(config: TrackerConfig) -> Result<Self, LocinetError> {
+        config.validate()?;
+        Ok(FaceTracker { config, tracks: Vec::new(), next_id: 0, last_timestamp_ns: None })
+    }
+
+    pub fn config(&self) -> &TrackerConfig {
+        &self.config
+    }
+
+    // Forget all tracks; the next faces start new ones
+    pub fn reset(&mut self) {
+        self.tracks.clear();
+        self.last_timestamp_ns = None;
+    }
+
+    // Advance the tracks to a frame captured at `timestamp_ns` and match its
+    // detections. Returns every live track: detected faces and lost ones.
+    pub fn update(&mut self, faces: &[Face], timestamp_ns: u64) -> Vec<TrackedFace> {
+        let dt = match self.last_timestamp_ns {
+            Some(last) => timestamp_ns.saturating_sub(last) as f32 / 1e9,
+            None => 0.0,
+        };
+        if dt * 1000.0 > self.config.reset_after_ms as f32 {
+            self.tracks.clear();
+        }
+        self.last_timestamp_ns = Some(timestamp_ns);
+
+        for track in &mut self.tracks {
+            track.predict(dt, &self.config);
+        }
+
+        // Match predictions to detections, maximizing total IoU. A NaN box
+        // (from a broken detection) gets an infinite cost and is never matched.
+        let cost: Vec<Vec<f32>> = self.tracks
+            .iter()
+            .map(|track| {
+                let predicted = track.predicted_bbox();
+                faces
+                    .iter()
+                    .map(|face| match iou(predicted, face.bbox) {
+                        overlap if overlap.is_finite() => 1.0 - overlap,
+                        _ => f32::INFINITY,
+                    })
+                    .collect()
+            })
+            .collect();
+        let assignment = hungarian(&cost, faces.len());
+
+        let mut matched = vec![false; faces.len()];
+        for ((track, detection), track_cost) in self.tracks.iter_mut().zip(assignment).zip(&cost) {
+            let Some(detection) = detection else { continue };
+            if 1.0 - track_cost[detection] >= self.config.iou_threshold {
+                track.update(faces[detection], &self.config);
+                matched[detection] = true;
+            }
+        }
+
+        // Retire tracks: tentative ones on their first miss, confirmed ones after max_age
+        let max_age = self.config.max_age;
+        self.tracks.retain(|track| {
+            track.frames_since_seen == 0 || (track.confirmed && track.frames_since_seen <= max_age)
+        });
+
+        // Unmatched detections start new tracks
+        for (face, _) in faces.iter().zip(&matched).filter(|(_, &matched)| !matched) {
+            self.tracks.push(Track::new(self.next_id, *face, &self.config));
+            self.next_id += 1;
+        }
+
+        let smoothing = &self.config.smoothing;
+        self.tracks.iter_mut().map(|track| track.tracked_face(dt, smoothing)).collect()
+    }
+}
+
+// Minimum-cost assignment of rows to columns (Hungarian algorithm with
+// potentials, O(n^2 m)). Returns the column assigned to each row; rows are
+// left unassigned only when there are more rows than columns. Non-finite
+// costs are assigned only when there is no way around them.
+fn hungarian(cost: &[Vec<f32>], columns: usize) -> Vec<Option<usize>> {
+    let rows = cost.len();
+    if rows == 0 || columns == 0 {
+        return vec![None; rows];
+    }
+    if cost.iter().flatten().any(|c| !c.is_finite()) {
+        // NaN or infinite costs would keep the search from ever finding a
+        // column, so replace them with a cost higher than any assignment
+        // that avoids them
+        let finite = cost.iter().flatten().copied().filter(|c| c.is_finite());
+        let largest = finite.fold(0.0f32, |largest, c| largest.max(c.abs()));
+        let worst = (largest + 1.0) * 2.0 * rows.min(columns) as f32;
+        let cost: Vec<Vec<f32>> = cost
+            .iter()
+            .map(|row| row.iter().map(|&c| if c.is_finite() { c } else { worst }).collect())
+            .collect();
+        return hungarian(&cost, columns);
+    }
+    if rows > columns {
+        // Solve the transposed problem, which has fewer rows than columns
+        let transposed: Vec<Vec<f32>> = (0..columns)
+            .map(|j| cost.iter().map(|row| row[j]).collect())
+            .collect();
+        let mut assignment = vec![None; rows];
+        for (j, i) in hungarian(&transposed, rows).into_iter().enumerate() {
+            if let Some(i) = i {
+                assignment[i] = Some(j);
+            }
+        }
+        return assignment;
+    }
+
+    // 1-based indices; index 0 is a virtual column used as the search root
+    let mut u = vec![0.0f32; rows + 1];
+    let mut v = vec![0.0f32; columns + 1];
+    let mut row_of = vec![0usize; columns + 1];
+    let mut way = vec![0usize; columns + 1];
+
+    for i in 1..=rows {
+        row_of[0] = i;
+        let mut j0 = 0;
+        let mut min_to = vec![f32::INFINITY; columns + 1];
+        let mut used = vec![false; columns + 1];
+        loop {
+            used[j0] = true;
+            let i0 = row_of[j0];
+            let mut delta = f32::INFINITY;
+            let mut j1 = 0;
+            for j in 1..=columns {
+                if used[j] {
+                    continue;
+                }
+                let reduced = cost[i0 - 1][j - 1] - u[i0] - v[j];
+                if reduced < min_to[j] {
+                    min_to[j] = reduced;
+                    way[j] = j0;
+                }
+                if min_to[j] < delta {
+                    delta = min_to[j];
+                    j1 = j;
+                }
+            }
+            for j in 0..=columns {
+                if used[j] {
+                    u[row_of[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    min_to[j] -= delta;
+                }
+            }
+            j0 = j1;
+            if row_of[j0] == 0 {
+                break;
+            }
+        }
+        // Flip the augmenting path
+        loop {
+            let j1 = way[j0];
+            row_of[j0] = row_of[j1];
+            j0 = j1;
+            if j0 == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut assignment = vec![None; rows];
+    for j in 1..=columns {
+        if row_of[j] != 0 {
+            assignment[row_of[j] - 1] = Some(j - 1);
+        }
+    }
+    assignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME_NS: u64 = 33_000_000;
+
+    // Face with a `size` x `size` box at (x, y)
+    fn face(x: f32, y: f32, size: f32) -> Face {
+        let bbox = [y, x, y + size, x + size];
+        let center = [y + size / 2.0, x + size / 2.0];
+        Face::new(bbox, bbox, center, [[x + size / 2.0, y + size / 2.0]; 6], 0.9, 480, 640)
+    }
+
+    fn total_cost(cost: &[Vec<f32>], assignment: &[Option<usize>]) -> f32 {
+        assignment.iter().enumerate().filter_map(|(i, j)| j.map(|j| cost[i][j])).sum()
+    }
+
+    #[test]
+    fn hungarian_square() {
+        let cost = vec![
+            vec![4.0, 1.0, 3.0],
+            vec![2.0, 0.0, 5.0],
+            vec![3.0, 2.0, 2.0],
+        ];
+        let assignment = hungarian(&cost, 3);
+        assert_eq!(assignment, vec![Some(1), Some(0), Some(2)]);
+        assert_eq!(total_cost(&cost, &assignment), 5.0);
+    }
+
+    #[test]
+    fn hungarian_rectangular() {
+        // More columns than rows: every row gets a column
+        let wide = vec![
+            vec![9.0, 2.0, 7.0, 8.0],
+            vec![6.0, 4.0, 3.0, 7.0],
+        ];
+        assert_eq!(hungarian(&wide, 4), vec![Some(1), Some(2)]);
+
+        // More rows than columns: the most expensive row is left out
+        let tall = vec![
+            vec![1.0, 5.0],
+            vec![9.0, 9.0],
+            vec![4.0, 2.0],
+        ];
+        assert_eq!(hungarian(&tall, 2), vec![Some(0), None, Some(1)]);
+
+        assert_eq!(hungarian(&[], 3), Vec::<Option<usize>>::new());
+        assert_eq!(hungarian(&[vec![], vec![]], 0), vec![None, None]);
+    }
+
+    #[test]
+    fn hungarian_avoids_non_finite_costs() {
+        let cost = vec![
+            vec![f32::NAN, 0.5],
+            vec![0.2, f32::INFINITY],
+        ];
+        assert_eq!(hungarian(&cost, 2), vec![Some(1), Some(0)]);
+
+        let cost = vec![vec![f32::NAN, f32::NAN], vec![f32::NAN, 0.1]];
+        assert_eq!(hungarian(&cost, 2), vec![Some(0), Some(1)]);
+    }
+
+    #[test]
+    fn nan_detection_is_not_matched() {
+        let mut tracker = FaceTracker::new(TrackerConfig { min_hits: 1, ..TrackerConfig::default() }).unwrap();
+        tracker.update(&[face(100.0, 100.0, 50.0)], 0);
+
+        let faces = tracker.update(&[face(f32::NAN, 100.0, 50.0)], FRAME_NS);
+        assert_eq!(faces.len(), 2);
+        assert_eq!((faces[0].track_id, faces[0].state), (0, TrackState::Lost));
+        assert_eq!(faces[1].track_id, 1);
+    }
+
+    #[test]
+    fn new_rejects_invalid_config() {
+        assert!(FaceTracker::new(TrackerConfig { iou_threshold: 1.5, ..TrackerConfig::default() }).is_err());
+        assert!(FaceTracker::new(TrackerConfig { process_noise: 0.0, ..TrackerConfig::default() }).is_err());
+        assert!(FaceTracker::new(TrackerConfig::default()).is_ok());
+    }
+
+    #[test]
+    fn track_lifecycle() {
+        let config = TrackerConfig { min_hits: 3, max_age: 2, ..TrackerConfig::default() };
+        let mut tracker = FaceTracker::new(config).unwrap();
+        let mut timestamp_ns = 0;
+        let mut step = |tracker: &mut FaceTracker, faces: &[Face]| {
+            timestamp_ns += FRAME_NS;
+            tracker.update(faces, timestamp_ns)
+        };
+
+        // Tentative until seen in min_hits frames, moving slowly
+        for (frame, expected) in [TrackState::Tentative, TrackState::Tentative, TrackState::Confirmed].into_iter().enumerate() {
+            let faces = step(&mut tracker, &[face(100.0 + frame as f32, 100.0, 50.0)]);
+            assert_eq!(faces.len(), 1);
+            assert_eq!((faces[0].track_id, faces[0].state, faces[0].age), (0, expected, frame as u32));
+        }
+
+        // Lost for up to max_age frames, then removed
+        for frames_since_seen in 1..=2 {
+            let faces = step(&mut tracker, &[]);
+            assert_eq!(faces.len(), 1);
+            assert_eq!((faces[0].track_id, faces[0].state), (0, TrackState::Lost));
+            assert_eq!(faces[0].frames_since_seen, frames_since_seen);
+        }
+        assert!(step(&mut tracker, &[]).is_empty());
+
+        // A tentative track is dropped on its first miss
+        let faces = step(&mut tracker, &[face(300.0, 200.0, 50.0)]);
+        assert_eq!((faces[0].track_id, faces[0].state), (1, TrackState::Tentative));
+        assert!(step(&mut tracker, &[]).is_empty());
+    }
+
+    #[test]
+    fn lost_track_is_recovered() {
+        let config = TrackerConfig { min_hits: 1, max_age: 5, ..TrackerConfig::default() };
+        let mut tracker = FaceTracker::new(config).unwrap();
+        tracker.update(&[face(100.0, 100.0, 50.0)], 0);
+        assert_eq!(tracker.update(&[], FRAME_NS)[0].state, TrackState::Lost);
+
+        let faces = tracker.update(&[face(102.0, 100.0, 50.0)], 2 * FRAME_NS);
+        assert_eq!(faces.len(), 1);
+        assert_eq!((faces[0].track_id, faces[0].state, faces[0].frames_since_seen), (0, TrackState::Confirmed, 0));
+    }
+
+    #[test]
+    fn gap_resets_tracks() {
+        let config = TrackerConfig { min_hits: 1, reset_after_ms: 500, ..TrackerConfig::default() };
+        let mut tracker = FaceTracker::new(config).unwrap();
+        tracker.update(&[face(100.0, 100.0, 50.0)], 0);
+
+        // Within the limit the track continues
+        let faces = tracker.update(&[face(100.0, 100.0, 50.0)], 400_000_000);
+        assert_eq!(faces[0].track_id, 0);
+
+        // After a longer gap the same face starts a new track
+        let faces = tracker.update(&[face(100.0, 100.0, 50.0)], 1_000_000_000);
+        assert_eq!(faces.len(), 1);
+        assert_eq!((faces[0].track_id, faces[0].age), (1, 0));
+    }
+}
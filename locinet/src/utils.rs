@@ -1,101 +1,8 @@
-use image::{imageops, ImageBuffer, Rgb};
-use visioncore_plugin::{Face, Frame};
-
-#[derive(Debug)]
-pub struct ImageTensor {
-    pub data: Vec<f32>,
-}
-
-impl ImageTensor {
-    pub fn new(data: Vec<f32>, batch_size: usize, height: usize, width: usize, channels: usize) -> Self {
-        assert_eq!(
-            data.len(),
-            batch_size * height * width * channels,
-            "Data length must match the shape"
-        );
-        
-        Self { data }
-    }
-}
-
-pub fn pad_frame(frame: &Frame) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
-    let (width, height) = (frame.width, frame.height);
-    let target_size = width.max(height);
-    
-    // Convert the Frame's raw data into a Vec<u8>
-    let data_slice = unsafe {
-        std::slice::from_raw_parts(frame.data, frame.len)
-    };
-    let data_vec = data_slice.to_vec();
-    
-    // Convert the Frame's raw data into an ImageBuffer
-    let image = ImageBuffer::<Rgb<u8>, _>::from_raw(width, height, data_vec)
-        .expect("Failed to convert frame data to ImageBuffer");
-
-    // Create a blank cancas of target size
-    let mut padded_frame = ImageBuffer::new(target_size, target_size);
-
-    // Calculate the padding offsets
-    let offset_x = (target_size - width) / 2;
-    let offset_y = (target_size - height) / 2;
-
-    // Paste the original frame into the center of the padded canvas
-    for y in 0..height {
-        for x in 0..width {
-            let pixel = image.get_pixel(x, y);
-            padded_frame.put_pixel(x + offset_x, y + offset_y, *pixel);
-        }
-    }
-    
-    padded_frame
-}
-
-pub fn resize_image(image: &ImageBuffer<Rgb<u8>, Vec<u8>>, target_size: u32) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
-    let resized_image = imageops::resize(image,
-        target_size, target_size,
-        imageops::FilterType::Nearest);
-
-    resized_image
-}
-
-
-pub fn normalize_image(image: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> ImageTensor {
-    let data: Vec<f32> = image
-        .pixels()
-        .flat_map(|p| p.0.iter().map(|&v| v as f32 / 255.0))
-        .collect();
-
-    ImageTensor::new(data, 1, image.height() as usize, image.width() as usize, 3)
-}
-
-pub fn generate_anchors(input_size: i32) -> Vec<[f32; 4]> {
-    let mut anchors = Vec::with_capacity(896); // Pre-allocate for 896 anchors
-    let strides = [8, 16];
-    let anchors_per_cell = [2, 6];
-
-    for (stride, num_anchors) in strides.iter().zip(anchors_per_cell.iter()) {
-        let grid_size = input_size / stride;
-        let base_size = (*stride as f32) / (input_size as f32);
-
-        for y in 0..grid_size {
-            for x in 0..grid_size {
-                let cx = (x as f32 + 0.5) * (*stride as f32) / (input_size as f32);
-                let cy = (y as f32 + 0.5) * (*stride as f32) / (input_size as f32);
-
-                for i in 0..*num_anchors {
-                    let scale = if i % 2 == 0 { 1.0 } else { 1.5 };
-                    let anchor_w = base_size * scale;
-                    let anchor_h = base_size * scale;
-                    anchors.push([cy, cx, anchor_h, anchor_w]);
-                }
-            }
-        }
-    }
-
-    assert_eq!(anchors.len(), 896, "Expected 896 anchors");
-    anchors
-}
-
+use visioncore_plugin::{Face, NUM_FACE_KEYPOINTS};
+use crate::anchors::Anchor;
+use crate::config::NmsMode;
+use crate::error::LocinetError;
+use crate::preprocess::LetterboxTransform;
 
 fn calculate_iou(box1: [f32; 4], box2: [f32; 4]) -> f32 {
     let (y_min1, x_min1, y_max1, x_max1) = (box1[0], box1[1], box1[2], box1[3]);
@@ -118,35 +25,46 @@ fn calculate_iou(box1: [f32; 4], box2: [f32; 4]) -> f32 {
     inter_area / (box1_area + box2_area - inter_area)
 }
 
-pub fn get_faces_from_anchors(anchors: &Vec<[f32; 4]>,
+// Decode the six keypoints that follow the box in each 16-value delta. Each
+// keypoint is an (x, y) offset in model input pixels from the anchor center.
+fn decode_keypoints(anchor: &Anchor, delta: &[f32], input_size: f32) -> [[f32; 2]; NUM_FACE_KEYPOINTS] {
+    let mut keypoints = [[0.0; 2]; NUM_FACE_KEYPOINTS];
+    for (k, keypoint) in keypoints.iter_mut().enumerate() {
+        let x = anchor.x_center + delta[4 + 2 * k] / input_size * anchor.w;
+        let y = anchor.y_center + delta[5 + 2 * k] / input_size * anchor.h;
+        *keypoint = [x, y];
+    }
+    keypoints
+}
+
+pub fn get_faces_from_anchors(anchors: &[Anchor],
     deltas: &Vec<Vec<f32>>,
     scores: &Vec<f32>,
     input_size: f32,
     iou_threshold: f32,
-    image_h: i32, image_w: i32) -> Vec<Face> {
+    nms_mode: NmsMode,
+    transform: &LetterboxTransform) -> Result<Vec<Face>, LocinetError> {
     
     // Verify input lengths
-    assert_eq!(anchors.len(), deltas.len(), "Anchors and deltas must have the same length");
-    assert_eq!(anchors.len(), scores.len(), "Anchors and scores must have the same length");
-
-    // Initialize variables
-    let mut bboxes: Vec<[f32; 4]> = Vec::with_capacity(anchors.len());
-    let mut centers: Vec<[f32; 2]> = Vec::with_capacity(anchors.len());
-
-    for (anchor, delta) in anchors.iter().zip(deltas) {
-        // Normalize deltas
-        let dy = delta[0] / input_size;
-        let dx = delta[1] / input_size;
-        let dh = delta[2] / input_size;
-        let dw = delta[3] / input_size;
+    for (tensor, len) in [("deltas", deltas.len()), ("scores", scores.len())] {
+        if len != anchors.len() {
+            return Err(LocinetError::ShapeMismatch {
+                tensor: tensor.to_string(),
+                expected: vec![anchors.len()],
+                actual: vec![len],
+            });
+        }
+    }
 
-        // Adjust center
-        let cy = anchor[0] + dy * anchor[2];
-        let cx: f32 = anchor[1] + dx * anchor[3];
+    let mut candidates: Vec<Candidate> = Vec::with_capacity(anchors.len());
 
-        // Adjust size
-        let h = dh;
-        let w = dw;
+    for ((anchor, delta), &score) in anchors.iter().zip(deltas).zip(scores) {
+        // Box regression as in MediaPipe's TensorsToDetectionsCalculator:
+        // center offset and size in input pixels, scaled by the anchor size
+        let cx = anchor.x_center + delta[0] / input_size * anchor.w;
+        let cy = anchor.y_center + delta[1] / input_size * anchor.h;
+        let w = delta[2] / input_size * anchor.w;
+        let h = delta[3] / input_size * anchor.h;
 
         // Convert to [y_min, x_min, y_max, x_max] format
         let y_min = (cy - h / 2.0).clamp(0.0, 1.0);
@@ -154,33 +72,185 @@ pub fn get_faces_from_anchors(anchors: &Vec<[f32; 4]>,
         let y_max = (cy + h / 2.0).clamp(0.0, 1.0);
         let x_max = (cx + w / 2.0).clamp(0.0, 1.0);
 
-        bboxes.push([y_min, x_min, y_max, x_max]);
-        centers.push([cy, cx]);
+        candidates.push(Candidate {
+            bbox: [y_min, x_min, y_max, x_max],
+            center: [cy, cx],
+            keypoints: decode_keypoints(anchor, delta, input_size),
+            score,
+        });
     };
 
     // Sort by scores in descending order
-    let mut indices: Vec<usize> = (0..scores.len()).collect();
-    indices.sort_by(|&a, &b| scores[b].partial_cmp(&scores[a]).unwrap_or(std::cmp::Ordering::Equal));
+    candidates.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+
+    let detections = match nms_mode {
+        NmsMode::Greedy => greedy_nms(candidates, iou_threshold),
+        NmsMode::Weighted => weighted_nms(candidates, iou_threshold),
+    };
+
+    // Undo the letterbox: model coordinates are relative to the padded square
+    Ok(detections
+        .into_iter()
+        .map(|detection| {
+            let [cy, cx] = detection.center;
+            let [x, y] = transform.to_frame([cx, cy]);
+            Face::new(
+                transform.bbox_to_frame(detection.bbox),
+                detection.bbox,
+                [y, x],
+                detection.keypoints.map(|keypoint| transform.to_frame(keypoint)),
+                detection.score,
+                transform.frame_h as i32,
+                transform.frame_w as i32,
+            )
+        })
+        .collect())
+}
+
+// One decoded detection in normalized model coordinates
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    bbox: [f32; 4], // [y_min, x_min, y_max, x_max]
+    center: [f32; 2], // [y, x]
+    keypoints: [[f32; 2]; NUM_FACE_KEYPOINTS], // [x, y]
+    score: f32,
+}
+
+// Keep the best candidate of each overlapping group and drop the rest.
+// `candidates` must be sorted by descending score.
+fn greedy_nms(candidates: Vec<Candidate>, iou_threshold: f32) -> Vec<Candidate> {
+    let mut kept: Vec<Candidate> = Vec::new();
+    for candidate in candidates {
+        if kept.iter().all(|k| calculate_iou(candidate.bbox, k.bbox) <= iou_threshold) {
+            kept.push(candidate);
+        }
+    }
+    kept
+}
 
-    // Apply non-maximum suppression (NMS)
-    let mut faces: Vec<Face> = Vec::new();
+// MediaPipe's weighted NMS: the best candidate and everything overlapping it
+// are merged into one detection, averaging box, center and keypoints weighted
+// by score. The merged detection keeps the best score. `candidates` must be
+// sorted by descending score.
+fn weighted_nms(candidates: Vec<Candidate>, iou_threshold: f32) -> Vec<Candidate> {
+    let mut merged = Vec::new();
+    let mut used = vec![false; candidates.len()];
+    for (i, best) in candidates.iter().enumerate() {
+        if used[i] {
+            continue;
+        }
+        let mut group = vec![best];
+        for (j, candidate) in candidates.iter().enumerate().skip(i + 1) {
+            if !used[j] && calculate_iou(best.bbox, candidate.bbox) > iou_threshold {
+                used[j] = true;
+                group.push(candidate);
+            }
+        }
 
-    for i in indices {
-        let bbox = bboxes[i];
-        let mut keep = true;
+        if group.len() == 1 {
+            merged.push(*best);
+            continue;
+        }
 
-        for &face in &faces {
-            if calculate_iou(bbox, face.bbox_raw) > iou_threshold {
-                keep = false;
-                break;
+        let total_score: f32 = group.iter().map(|c| c.score).sum();
+        let mut blended = Candidate { bbox: [0.0; 4], center: [0.0; 2], keypoints: [[0.0; 2]; NUM_FACE_KEYPOINTS], score: best.score };
+        for c in group {
+            let weight = c.score / total_score;
+            for i in 0..4 {
+                blended.bbox[i] += c.bbox[i] * weight;
+            }
+            for i in 0..2 {
+                blended.center[i] += c.center[i] * weight;
             }
+            for (blended_keypoint, keypoint) in blended.keypoints.iter_mut().zip(&c.keypoints) {
+                blended_keypoint[0] += keypoint[0] * weight;
+                blended_keypoint[1] += keypoint[1] * weight;
+            }
+        }
+        merged.push(blended);
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-3, "expected {}, got {}", expected, actual);
+    }
+
+    #[test]
+    fn decodes_one_anchor_into_frame_pixels() {
+        // BlazeFace anchors have unit size, so deltas are plain input pixels
+        let anchors = [Anchor { x_center: 0.5, y_center: 0.25, w: 1.0, h: 1.0 }];
+        let input_size = 128.0;
+        // Box centered 12.8 px right and 6.4 px below the anchor, 32 px square
+        let mut delta = vec![12.8, 6.4, 32.0, 32.0];
+        // Keypoint k is (k - 2.5) * 12.8 px right and k * 6.4 px below the anchor
+        for k in 0..NUM_FACE_KEYPOINTS {
+            delta.push((k as f32 - 2.5) * 12.8);
+            delta.push(k as f32 * 6.4);
         }
 
-        if keep {
-            faces.push(Face::new(bbox, centers[i], scores[i], image_h, image_w));
+        // A 640x480 frame is padded to a 640 px square, 80 px above and below
+        let transform = LetterboxTransform::new(640, 480);
+        let faces = get_faces_from_anchors(&anchors, &vec![delta], &vec![0.9], input_size, 0.3, NmsMode::Greedy, &transform).unwrap();
+        assert_eq!(faces.len(), 1);
+        let face = &faces[0];
+
+        // Normalized center (0.6, 0.3), box 0.25 on each side
+        assert_close(face.center[0], 0.3 * 640.0 - 80.0);
+        assert_close(face.center[1], 0.6 * 640.0);
+        for (actual, expected) in face.bbox.iter().zip([304.0, 32.0, 160.0, 160.0]) {
+            assert_close(*actual, expected);
+        }
+        for (k, keypoint) in face.keypoints.iter().enumerate() {
+            assert_close(keypoint[0], 320.0 + (k as f32 - 2.5) * 64.0);
+            assert_close(keypoint[1], 80.0 + k as f32 * 32.0);
+        }
+        assert_close(face.score, 0.9);
+        assert_eq!((face.frame_w, face.frame_h), (640, 480));
+    }
+
+    fn candidate(bbox: [f32; 4], keypoint: [f32; 2], score: f32) -> Candidate {
+        let [y_min, x_min, y_max, x_max] = bbox;
+        Candidate {
+            bbox,
+            center: [(y_min + y_max) / 2.0, (x_min + x_max) / 2.0],
+            keypoints: [keypoint; NUM_FACE_KEYPOINTS],
+            score,
         }
     }
 
-    faces
+    #[test]
+    fn weighted_nms_blends_overlapping_candidates() {
+        // The first two overlap with an IoU of 0.6, the third is elsewhere
+        let candidates = vec![
+            candidate([0.0, 0.0, 0.4, 0.4], [0.1, 0.2], 0.9),
+            candidate([0.0, 0.1, 0.4, 0.5], [0.2, 0.3], 0.3),
+            candidate([0.6, 0.6, 0.8, 0.8], [0.7, 0.7], 0.5),
+        ];
+        let merged = weighted_nms(candidates.clone(), 0.3);
+        assert_eq!(merged.len(), 2);
+
+        // Weights 0.75 and 0.25
+        let blended = merged[0];
+        for (actual, expected) in blended.bbox.iter().zip([0.0, 0.025, 0.4, 0.425]) {
+            assert_close(*actual, expected);
+        }
+        assert_close(blended.center[0], 0.2);
+        assert_close(blended.center[1], 0.225);
+        for keypoint in blended.keypoints {
+            assert_close(keypoint[0], 0.125);
+            assert_close(keypoint[1], 0.225);
+        }
+        assert_eq!(blended.score, 0.9);
 
-}
\ No newline at end of file
+        let separate = merged[1];
+        assert_eq!(separate.bbox, candidates[2].bbox);
+        assert_eq!(separate.center, candidates[2].center);
+        assert_eq!(separate.keypoints, candidates[2].keypoints);
+        assert_eq!(separate.score, candidates[2].score);
+    }
+}
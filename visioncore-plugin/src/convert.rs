@@ -0,0 +1,237 @@
+// Pixel format conversions shared by VisionCore and its sub-services.
+//
+// YUV formats are decoded as BT.601 limited range, which is what USB and CSI
+// cameras deliver.
+
+use crate::{FrameRef, PixelFormat};
+
+// RGB value of the pixel at (x, y). The frame must have passed
+// `FrameRef::validate` and (x, y) must be inside it.
+#[inline]
+pub fn rgb_at(frame: &FrameRef, x: u32, y: u32) -> [u8; 3] {
+    let stride = frame.stride as usize;
+    let (x, y) = (x as usize, y as usize);
+    let row = &frame.data[y * stride..];
+
+    match frame.format {
+        PixelFormat::Rgb => [row[x * 3], row[x * 3 + 1], row[x * 3 + 2]],
+        PixelFormat::Bgr => [row[x * 3 + 2], row[x * 3 + 1], row[x * 3]],
+        PixelFormat::Rgba => [row[x * 4], row[x * 4 + 1], row[x * 4 + 2]],
+        PixelFormat::Bgra => [row[x * 4 + 2], row[x * 4 + 1], row[x * 4]],
+        PixelFormat::Gray => [row[x]; 3],
+        PixelFormat::Yuyv => {
+            // Two pixels share one U and one V: Y0 U Y1 V
+            let pair = (x / 2) * 4;
+            let luma = row[pair + (x % 2) * 2];
+            yuv_to_rgb(luma, row[pair + 1], row[pair + 3])
+        }
+        PixelFormat::Nv12 => {
+            let uv_plane = &frame.data[stride * frame.height as usize..];
+            let uv = (y / 2) * stride + (x / 2) * 2;
+            yuv_to_rgb(row[x], uv_plane[uv], uv_plane[uv + 1])
+        }
+    }
+}
+
+#[inline]
+pub fn yuv_to_rgb(y: u8, u: u8, v: u8) -> [u8; 3] {
+    let c = (y as i32 - 16).max(0) * 298;
+    let d = u as i32 - 128;
+    let e = v as i32 - 128;
+
+    let r = (c + 409 * e + 128) >> 8;
+    let g = (c - 100 * d - 208 * e + 128) >> 8;
+    let b = (c + 516 * d + 128) >> 8;
+    [r.clamp(0, 255) as u8, g.clamp(0, 255) as u8, b.clamp(0, 255) as u8]
+}
+
+#[inline]
+pub fn rgb_to_gray(rgb: [u8; 3]) -> u8 {
+    ((77 * rgb[0] as u32 + 150 * rgb[1] as u32 + 29 * rgb[2] as u32) >> 8) as u8
+}
+
+// Convert a frame to a packed RGB, BGR, RGBA, BGRA or GRAY buffer with no row
+// padding. Returns the stride of the converted frame.
+pub fn convert_into(frame: &FrameRef, target: PixelFormat, out: &mut Vec<u8>) -> Result<u32, String> {
+    frame.validate()?;
+
+    let bytes_per_pixel = match target {
+        PixelFormat::Rgb | PixelFormat::Bgr | PixelFormat::Rgba | PixelFormat::Bgra | PixelFormat::Gray => {
+            target.bytes_per_pixel()
+        }
+        PixelFormat::Nv12 | PixelFormat::Yuyv => {
+            return Err(format!("Conversion to {:?} is not supported", target));
+        }
+    };
+
+    out.clear();
+    out.reserve(frame.width as usize * frame.height as usize * bytes_per_pixel);
+
+    // Same layout: copy rows and drop the padding
+    if frame.format == target {
+        let row_len = target.min_stride(frame.width);
+        for y in 0..frame.height as usize {
+            let start = y * frame.stride as usize;
+            out.extend_from_slice(&frame.data[start..start + row_len]);
+        }
+        return Ok(row_len as u32);
+    }
+
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            let [r, g, b] = rgb_at(frame, x, y);
+            match target {
+                PixelFormat::Rgb => out.extend_from_slice(&[r, g, b]),
+                PixelFormat::Bgr => out.extend_from_slice(&[b, g, r]),
+                PixelFormat::Rgba => out.extend_from_slice(&[r, g, b, 255]),
+                PixelFormat::Bgra => out.extend_from_slice(&[b, g, r, 255]),
+                PixelFormat::Gray => out.push(rgb_to_gray([r, g, b])),
+                PixelFormat::Nv12 | PixelFormat::Yuyv => unreachable!(),
+            }
+        }
+    }
+
+    Ok(target.min_stride(frame.width) as u32)
+}
+
+// Convert a frame to tightly packed RGB
+pub fn to_rgb(frame: &FrameRef) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    convert_into(frame, PixelFormat::Rgb, &mut out)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: [u8; 3] = [0, 0, 0];
+    const WHITE: [u8; 3] = [255, 255, 255];
+    const GRAY: [u8; 3] = [128, 128, 128];
+    // Y 81, U 90, V 240
+    const RED: [u8; 3] = [255, 0, 0];
+    // Y 126, U 90, V 240
+    const SALMON: [u8; 3] = [255, 52, 51];
+
+    #[test]
+    fn yuv_reference_colors() {
+        assert_eq!(yuv_to_rgb(16, 128, 128), BLACK);
+        assert_eq!(yuv_to_rgb(235, 128, 128), WHITE);
+        assert_eq!(yuv_to_rgb(126, 128, 128), GRAY);
+        assert_eq!(yuv_to_rgb(81, 90, 240), RED);
+        assert_eq!(yuv_to_rgb(126, 90, 240), SALMON);
+    }
+
+    #[test]
+    fn nv12_pixels_share_chroma_per_2x2_block() {
+        // 4x2 with a stride of 6: two Y rows, then one UV row of U0 V0 U1 V1
+        #[rustfmt::skip]
+        let data = [
+            16, 235, 126, 81, 0, 0,
+            235, 16, 81, 126, 0, 0,
+            128, 128, 90, 240, 0, 0,
+        ];
+        let frame = FrameRef::new(&data, 4, 2, PixelFormat::Nv12, 6);
+        frame.validate().unwrap();
+
+        assert_eq!(rgb_at(&frame, 0, 0), BLACK);
+        assert_eq!(rgb_at(&frame, 1, 0), WHITE);
+        assert_eq!(rgb_at(&frame, 2, 0), SALMON);
+        assert_eq!(rgb_at(&frame, 3, 0), RED);
+        assert_eq!(rgb_at(&frame, 0, 1), WHITE);
+        assert_eq!(rgb_at(&frame, 1, 1), BLACK);
+        assert_eq!(rgb_at(&frame, 2, 1), RED);
+        assert_eq!(rgb_at(&frame, 3, 1), SALMON);
+    }
+
+    #[test]
+    fn nv12_odd_size_rounds_chroma_up() {
+        // 3x3: the last column and row still have a chroma sample
+        #[rustfmt::skip]
+        let data = [
+            16, 16, 126, 0,
+            16, 16, 126, 0,
+            126, 126, 81, 0,
+            128, 128, 90, 240,
+            128, 128, 90, 240,
+        ];
+        let frame = FrameRef::new(&data, 3, 3, PixelFormat::Nv12, 4);
+        frame.validate().unwrap();
+
+        assert_eq!(rgb_at(&frame, 0, 0), BLACK);
+        assert_eq!(rgb_at(&frame, 2, 0), SALMON);
+        assert_eq!(rgb_at(&frame, 0, 2), GRAY);
+        assert_eq!(rgb_at(&frame, 2, 2), RED);
+
+        // A stride of 3 leaves no room for the last V sample
+        assert!(FrameRef::new(&data, 3, 3, PixelFormat::Nv12, 3).validate().is_err());
+    }
+
+    #[test]
+    fn yuyv_pixels_share_chroma_per_pair() {
+        // Y0 U Y1 V for pixels (0, 1) and (2, 3)
+        let data = [16, 128, 235, 128, 126, 90, 81, 240];
+        let frame = FrameRef::new(&data, 4, 1, PixelFormat::Yuyv, 8);
+        frame.validate().unwrap();
+
+        assert_eq!(rgb_at(&frame, 0, 0), BLACK);
+        assert_eq!(rgb_at(&frame, 1, 0), WHITE);
+        assert_eq!(rgb_at(&frame, 2, 0), SALMON);
+        assert_eq!(rgb_at(&frame, 3, 0), RED);
+    }
+
+    #[test]
+    fn yuyv_odd_width_reads_last_pair() {
+        // Three pixels take two whole pairs; the fourth luma is padding
+        #[rustfmt::skip]
+        let data = [
+            235, 128, 16, 128, 81, 90, 0, 240,
+            16, 128, 235, 128, 126, 90, 0, 240,
+        ];
+        let frame = FrameRef::new(&data, 3, 2, PixelFormat::Yuyv, 8);
+        frame.validate().unwrap();
+
+        assert_eq!(rgb_at(&frame, 0, 0), WHITE);
+        assert_eq!(rgb_at(&frame, 1, 0), BLACK);
+        assert_eq!(rgb_at(&frame, 2, 0), RED);
+        assert_eq!(rgb_at(&frame, 2, 1), SALMON);
+
+        // Six bytes per row would cut the last pair in half
+        assert_eq!(PixelFormat::Yuyv.min_stride(3), 8);
+        assert!(FrameRef::new(&data[..14], 3, 2, PixelFormat::Yuyv, 6).validate().is_err());
+    }
+
+    #[test]
+    fn bgr_swaps_channels() {
+        // 2x2 with one byte of padding per row
+        #[rustfmt::skip]
+        let data = [
+            1, 2, 3, 4, 5, 6, 0,
+            7, 8, 9, 10, 11, 12, 0,
+        ];
+        let frame = FrameRef::new(&data, 2, 2, PixelFormat::Bgr, 7);
+        frame.validate().unwrap();
+
+        assert_eq!(rgb_at(&frame, 0, 0), [3, 2, 1]);
+        assert_eq!(rgb_at(&frame, 1, 0), [6, 5, 4]);
+        assert_eq!(rgb_at(&frame, 0, 1), [9, 8, 7]);
+        assert_eq!(rgb_at(&frame, 1, 1), [12, 11, 10]);
+    }
+
+    #[test]
+    fn rgba_drops_alpha() {
+        // 2x2 with four bytes of padding per row
+        #[rustfmt::skip]
+        let data = [
+            1, 2, 3, 255, 4, 5, 6, 128, 0, 0, 0, 0,
+            7, 8, 9, 0, 10, 11, 12, 255, 0, 0, 0, 0,
+        ];
+        let frame = FrameRef::new(&data, 2, 2, PixelFormat::Rgba, 12);
+        frame.validate().unwrap();
+
+        assert_eq!(rgb_at(&frame, 0, 0), [1, 2, 3]);
+        assert_eq!(rgb_at(&frame, 1, 0), [4, 5, 6]);
+        assert_eq!(rgb_at(&frame, 0, 1), [7, 8, 9]);
+        assert_eq!(rgb_at(&frame, 1, 1), [10, 11, 12]);
+    }
+}
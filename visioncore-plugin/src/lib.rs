@@ -1,71 +1,210 @@
+pub mod convert;
+
 use serde::{Serialize, Deserialize};
 
+// Pixel layout of a frame's data. Packed formats store `stride` bytes per
+// row. NV12 stores a full-resolution Y plane of `height` rows followed by a
+// half-resolution interleaved UV plane of `height / 2` rows, both `stride`
+// bytes wide.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PixelFormat {
+    Rgb,
+    Bgr,
+    Rgba,
+    Bgra,
+    Nv12,
+    Yuyv,
+    Gray,
+}
+
+impl PixelFormat {
+    // Bytes per pixel of the first (or only) plane
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelFormat::Rgb | PixelFormat::Bgr => 3,
+            PixelFormat::Rgba | PixelFormat::Bgra => 4,
+            PixelFormat::Yuyv => 2,
+            PixelFormat::Nv12 | PixelFormat::Gray => 1,
+        }
+    }
+
+    // Smallest stride that fits a row of `width` pixels. YUYV and the NV12
+    // UV plane store chroma per pair of pixels, so an odd width is rounded
+    // up to a whole pair.
+    pub fn min_stride(&self, width: u32) -> usize {
+        match self {
+            PixelFormat::Yuyv | PixelFormat::Nv12 => width.div_ceil(2) as usize * 2 * self.bytes_per_pixel(),
+            _ => width as usize * self.bytes_per_pixel(),
+        }
+    }
+
+    // Bytes needed for a frame of `height` rows of `stride` bytes
+    pub fn buffer_len(&self, stride: usize, height: u32) -> usize {
+        let height = height as usize;
+        match self {
+            PixelFormat::Nv12 => stride * (height + height.div_ceil(2)),
+            _ => stride * height,
+        }
+    }
+}
+
 // Types for frames, landmarks, and faces
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct Frame {
-    pub data: *const u8, // Pointer to RGB data
+    pub data: *const u8, // Pointer to pixel data
     pub len: usize,      // Length of data
     pub width: u32,
     pub height: u32,
+    pub format: PixelFormat,
+    pub stride: u32,       // Bytes per row
+    pub seq: u64,          // Per-camera frame sequence number
+    pub timestamp_ns: u64, // Capture time on CLOCK_MONOTONIC
 }
 
-#[repr(C)]
+// Borrowed view of a frame. The lifetime ties it to the buffer holding the
+// pixels, so the data cannot be freed or replaced while a sub-service is
+// still reading it. Convert to `Frame` only at the C plugin boundary.
 #[derive(Debug, Clone, Copy)]
+pub struct FrameRef<'a> {
+    pub data: &'a [u8],
+    pub width: u32,
+    pub height: u32,
+    pub format: PixelFormat,
+    pub stride: u32,
+    pub seq: u64,
+    pub timestamp_ns: u64,
+}
+
+impl<'a> FrameRef<'a> {
+    pub fn new(data: &'a [u8], width: u32, height: u32, format: PixelFormat, stride: u32) -> Self {
+        Self { data, width, height, format, stride, seq: 0, timestamp_ns: 0 }
+    }
+
+    // Tightly packed RGB frame
+    pub fn rgb(data: &'a [u8], width: u32, height: u32) -> Self {
+        Self::new(data, width, height, PixelFormat::Rgb, width * 3)
+    }
+
+    pub fn with_timing(self, seq: u64, timestamp_ns: u64) -> Self {
+        Self { seq, timestamp_ns, ..self }
+    }
+
+    /// Wraps a raw frame received over the plugin interface.
+    ///
+    /// # Safety
+    ///
+    /// `frame.data` must point to `frame.len` readable bytes that stay valid
+    /// and unmodified for `'a`.
+    pub unsafe fn from_raw(frame: &Frame) -> Self {
+        let data = if frame.len == 0 {
+            &[]
+        } else {
+            unsafe { std::slice::from_raw_parts(frame.data, frame.len) }
+        };
+        Self::new(data, frame.width, frame.height, frame.format, frame.stride)
+            .with_timing(frame.seq, frame.timestamp_ns)
+    }
+
+    // Raw frame for the plugin interface. It points into `self.data`, so it
+    // must not outlive the borrow this `FrameRef` was made from.
+    pub fn as_raw(&self) -> Frame {
+        Frame {
+            data: self.data.as_ptr(),
+            len: self.data.len(),
+            width: self.width,
+            height: self.height,
+            format: self.format,
+            stride: self.stride,
+            seq: self.seq,
+            timestamp_ns: self.timestamp_ns,
+        }
+    }
+
+    // Check that the frame is non-empty, the stride fits a row and the data
+    // covers every row
+    pub fn validate(&self) -> Result<(), String> {
+        if self.width == 0 || self.height == 0 {
+            return Err(format!("Frame has no pixels ({}x{})", self.width, self.height));
+        }
+        let min_stride = self.format.min_stride(self.width);
+        if (self.stride as usize) < min_stride {
+            return Err(format!(
+                "Stride {} is smaller than a {:?} row of {} pixels ({} bytes)",
+                self.stride, self.format, self.width, min_stride
+            ));
+        }
+        let expected = self.format.buffer_len(self.stride as usize, self.height);
+        if self.data.len() < expected {
+            return Err(format!(
+                "{:?} frame {}x{} with stride {} needs {} bytes, got {}",
+                self.format, self.width, self.height, self.stride, expected, self.data.len()
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Landmark {
     pub x: f32,
     pub y: f32,
     pub z: f32,
 }
 
+// Facial keypoints predicted by BlazeFace, in the order of `Face::keypoints`.
+// Left and right are from the subject's point of view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaceKeypoint {
+    RightEye = 0,
+    LeftEye = 1,
+    NoseTip = 2,
+    Mouth = 3,
+    RightEarTragion = 4,
+    LeftEarTragion = 5,
+}
+
+pub const NUM_FACE_KEYPOINTS: usize = 6;
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Face {
-    pub bbox: [f32; 4],
-    pub bbox_raw: [f32; 4],
-    pub center: [f32; 2],
+    pub bbox: [f32; 4], // [x, y, w, h] in frame pixels
+    pub bbox_raw: [f32; 4], // [y_min, x_min, y_max, x_max] normalized to the (letterboxed) model input
+    pub center: [f32; 2], // [y, x] in frame pixels
+    pub keypoints: [[f32; 2]; NUM_FACE_KEYPOINTS], // [x, y] in frame pixels, indexed by FaceKeypoint
     pub score: f32,
     pub frame_h: i32,
     pub frame_w: i32
 }
 
 impl Face {
-    pub fn new(bbox: [f32; 4], center: [f32; 2], score: f32, image_h: i32, image_w: i32) -> Self {
-        let [y_min, x_min, y_max, x_max]  = Face::scale_bbox(bbox, image_h as f32, image_w as f32);
-        let center = Face::scale_center(center, image_h as f32, image_w as f32);
+    // `bbox` is [y_min, x_min, y_max, x_max], and `center` and `keypoints` are
+    // already mapped back to frame pixels by the detector
+    pub fn new(bbox: [f32; 4], bbox_raw: [f32; 4], center: [f32; 2], keypoints: [[f32; 2]; NUM_FACE_KEYPOINTS], score: f32, image_h: i32, image_w: i32) -> Self {
+        let [y_min, x_min, y_max, x_max] = bbox;
 
         Self { 
             bbox: [
-                x_min as f32,
-                y_min as f32,
-                (x_max - x_min),
-                (y_max - y_min),
+                x_min,
+                y_min,
+                x_max - x_min,
+                y_max - y_min,
             ],
-            bbox_raw: bbox,
+            bbox_raw,
             center,
+            keypoints,
             score,
             frame_h: image_h,
             frame_w: image_w
         }
     }
 
-    fn scale_bbox(bbox: [f32; 4], image_h: f32, image_w: f32) -> [f32; 4] {
-        let (y_min, x_min, y_max, x_max) = (bbox[0], bbox[1], bbox[2], bbox[3]);
-    
-        // Scale the bbox to the original image
-        let y_min = y_min * image_h;
-        let x_min = x_min * image_w;
-        let y_max = y_max * image_h;
-        let x_max = x_max * image_w;
-        
-        [y_min, x_min, y_max, x_max]
-    }
-
-    fn scale_center(center: [f32; 2], image_h: f32, image_w: f32) -> [f32; 2] {
-        let (y, x) = (center[0], center[1]);
-        let y = y * image_h;
-        let x = x * image_w;
-        [y, x]
+    pub fn keypoint(&self, keypoint: FaceKeypoint) -> [f32; 2] {
+        self.keypoints[keypoint as usize]
     }
 }
 
@@ -78,4 +217,20 @@ pub struct PluginInterface {
     pub detect_landmarks: Option<DetectLandmarksFn>,
     pub detect_faces: Option<DetectFacesFn>,
     pub free_faces: Option<unsafe extern "C" fn(*mut Face, usize)>,
+    pub free_landmarks: Option<unsafe extern "C" fn(*mut Landmark, usize)>,
+    // Pixel formats the plugin reads natively; frames in other formats must
+    // be converted (see `convert`) before they are passed in
+    pub accepted_formats: *const PixelFormat,
+    pub num_accepted_formats: usize,
+}
+
+impl PluginInterface {
+    pub fn accepts(&self, format: PixelFormat) -> bool {
+        if self.accepted_formats.is_null() {
+            return false;
+        }
+        // The plugin guarantees the array outlives its interface
+        let formats = unsafe { std::slice::from_raw_parts(self.accepted_formats, self.num_accepted_formats) };
+        formats.contains(&format)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,159 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Error;
+use serde::Serialize;
+
+use crate::config::{ReconnectConfig, SourceConfig};
+use crate::frame::FrameSlot;
+use crate::publisher::Publisher;
+use crate::source::{self, FrameSource, SourceCaps};
+
+// Published on `<prefix>/<camera>/camera_state` whenever the camera changes state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CameraState {
+    // First successful open
+    Streaming,
+    // Open failed, capture errored or stalled; reconnection is in progress
+    Lost,
+    // Streaming again after being lost
+    Restored,
+    // The source reached the end of its stream (recordings only)
+    Ended,
+}
+
+#[derive(Serialize)]
+struct CameraStateEvent<'a> {
+    camera: &'a str,
+    state: CameraState,
+    reason: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    fps: Option<f32>,
+}
+
+// Keeps a camera streaming into its frame slot: reopens the source with
+// exponential backoff after errors or stalls and reports state changes.
+pub struct CaptureSupervisor {
+    id: Arc<str>,
+    source_config: SourceConfig,
+    reconnect: ReconnectConfig,
+    frame_slot: Arc<FrameSlot>,
+    publisher: Arc<Publisher>,
+    state: Option<CameraState>,
+    // Sequence number of the next frame; keeps counting across reconnections
+    next_seq: u64,
+}
+
+impl CaptureSupervisor {
+    pub fn new(
+        id: Arc<str>,
+        source_config: SourceConfig,
+        reconnect: ReconnectConfig,
+        frame_slot: Arc<FrameSlot>,
+        publisher: Arc<Publisher>,
+    ) -> Self {
+        CaptureSupervisor {
+            id,
+            source_config,
+            reconnect,
+            frame_slot,
+            publisher,
+            state: None,
+            next_seq: 0,
+        }
+    }
+
+    pub fn run(&mut self) -> Result<(), Error> {
+        let mut source = source::from_config(&self.source_config)?;
+        source.set_stall_timeout(Duration::from_millis(self.reconnect.stall_timeout_ms));
+
+        let initial_delay = Duration::from_millis(self.reconnect.initial_delay_ms);
+        let max_delay = Duration::from_millis(self.reconnect.max_delay_ms);
+        let mut delay = initial_delay;
+        let mut attempts = 0;
+
+        loop {
+            match source.open() {
+                Ok(caps) => {
+                    attempts = 0;
+                    delay = initial_delay;
+                    let state = match self.state {
+                        Some(CameraState::Lost) => CameraState::Restored,
+                        _ => CameraState::Streaming,
+                    };
+                    self.set_state(state, None, Some(caps));
+
+                    match self.stream(source.as_mut()) {
+                        Ok(()) => {
+                            self.set_state(CameraState::Ended, None, None);
+                            return source.close();
+                        }
+                        Err(e) => {
+                            self.lost(format!("{:#}", e));
+                            if let Err(e) = source.close() {
+                                eprintln!("[{}] Failed to close source: {:?}", self.id, e);
+                            }
+                        }
+                    }
+                }
+                Err(e) => {
+                    attempts += 1;
+                    self.lost(format!("{:#}", e));
+                    if let Some(max_attempts) = self.reconnect.max_attempts {
+                        if attempts >= max_attempts {
+                            return Err(e.context(format!("Giving up after {} attempts", attempts)));
+                        }
+                    }
+                }
+            }
+
+            println!("[{}] Reconnecting in {:?}", self.id, delay);
+            thread::sleep(delay);
+            delay = (delay * 2).min(max_delay);
+        }
+    }
+
+    // Hand frames to detection until the stream ends or fails
+    fn stream(&mut self, source: &mut dyn FrameSource) -> Result<(), Error> {
+        while let Some(mut frame) = source.next_frame()? {
+            frame.camera = Arc::clone(&self.id);
+            frame.seq = self.next_seq;
+            self.next_seq += 1;
+
+            self.frame_slot.put(frame);
+        }
+        Ok(())
+    }
+
+    fn lost(&mut self, reason: String) {
+        eprintln!("[{}] Camera lost: {}", self.id, reason);
+
+        // Stop detection from running on a stale frame while the camera is gone
+        self.frame_slot.clear();
+
+        // Report the outage once, not on every failed reconnection attempt
+        if self.state != Some(CameraState::Lost) {
+            self.set_state(CameraState::Lost, Some(reason), None);
+        }
+    }
+
+    fn set_state(&mut self, state: CameraState, reason: Option<String>, caps: Option<SourceCaps>) {
+        println!("[{}] Camera state: {:?}", self.id, state);
+        self.state = Some(state);
+
+        let event = CameraStateEvent {
+            camera: &self.id,
+            state,
+            reason,
+            width: caps.map(|c| c.width),
+            height: caps.map(|c| c.height),
+            fps: caps.and_then(|c| c.fps),
+        };
+        if let Err(e) = self.publisher.publish(&self.id, "camera_state", &event) {
+            eprintln!("[{}] Failed to publish camera state: {:?}", self.id, e);
+        }
+    }
+}
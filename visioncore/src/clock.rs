@@ -0,0 +1,11 @@
+// Current CLOCK_MONOTONIC time in nanoseconds. This is the same clock that
+// GStreamer's system clock runs on, so capture timestamps from every live
+// source are comparable with each other and with subscribers on the same machine.
+pub fn monotonic_now_ns() -> u64 {
+    let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    // CLOCK_MONOTONIC is always available on Linux and `ts` is a valid pointer
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts);
+    }
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
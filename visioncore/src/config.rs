@@ -0,0 +1,202 @@
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Error};
+use locinet::{CameraIntrinsics, DetectorConfig, HeadPoseConfig, RecognitionConfig, TrackerConfig};
+use serde::Deserialize;
+
+// Service configuration, read from the JSON file named by VISIONCORE_CONFIG.
+// Every field has a default so the service still starts without a config file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub publisher_endpoint: String,
+    // Detections are published on `<topic_prefix>/<camera id>/face_position`
+    pub topic_prefix: String,
+    pub cameras: Vec<CameraConfig>,
+    // Face detector thresholds, limits and model for cameras that do not set their own
+    pub detector: DetectorConfig,
+    // Face tracking, run separately on each camera
+    pub tracker: TrackerConfig,
+    // Head pose of every detected face; null turns it off
+    pub head_pose: Option<HeadPoseConfig>,
+    // Identify faces against the enrolled users; off unless configured
+    pub recognition: Option<RecognitionConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            publisher_endpoint: "tcp://localhost:5555".to_string(),
+            topic_prefix: "VisionCore".to_string(),
+            cameras: vec![CameraConfig::default()],
+            detector: DetectorConfig::default(),
+            tracker: TrackerConfig::default(),
+            head_pose: Some(HeadPoseConfig::default()),
+            recognition: None,
+        }
+    }
+}
+
+// One capture + detection pipeline
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CameraConfig {
+    pub id: String,
+    pub enabled: bool,
+    pub source: SourceConfig,
+    pub reconnect: ReconnectConfig,
+    // Overrides the top-level detector config for this camera
+    pub detector: Option<DetectorConfig>,
+    // Calibration of this camera for head pose; overrides `head_pose.intrinsics`
+    pub intrinsics: Option<CameraIntrinsics>,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        CameraConfig {
+            id: "default".to_string(),
+            enabled: true,
+            source: SourceConfig::default(),
+            reconnect: ReconnectConfig::default(),
+            detector: None,
+            intrinsics: None,
+        }
+    }
+}
+
+// How the capture supervisor recovers from camera errors and stalls
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ReconnectConfig {
+    // Delay before the first reconnection attempt, doubled after each failure
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+    // A camera that delivers no frame for this long is treated as lost
+    pub stall_timeout_ms: u64,
+    // Give up after this many failed attempts in a row; None retries forever
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        ReconnectConfig {
+            initial_delay_ms: 500,
+            max_delay_ms: 10_000,
+            stall_timeout_ms: 3_000,
+            max_attempts: None,
+        }
+    }
+}
+
+// Which frame source backend to capture from
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SourceConfig {
+    // USB / V4L2 camera through nokhwa
+    Camera {
+        #[serde(default)]
+        index: u32,
+    },
+    // Jetson CSI camera through nvarguscamerasrc and an appsink
+    Csi,
+    // Any GStreamer pipeline ending in `appsink name=sink`
+    Gstreamer {
+        pipeline: String,
+    },
+    // Video file decoded through GStreamer `filesrc ! decodebin`
+    Video {
+        path: PathBuf,
+        #[serde(default)]
+        playback: Playback,
+        #[serde(default)]
+        looping: bool,
+    },
+    // Directory of PNG/JPEG files, played in file name order
+    Images {
+        path: PathBuf,
+        #[serde(default = "default_images_fps")]
+        fps: f32,
+        #[serde(default)]
+        playback: Playback,
+        #[serde(default)]
+        looping: bool,
+    },
+}
+
+// How recorded sources (video files, image directories) are replayed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Playback {
+    // At the recording's frame rate
+    #[default]
+    Native,
+    // As fast as frames can be decoded
+    Fast,
+    // One frame each time Enter is pressed on stdin
+    Step,
+}
+
+fn default_images_fps() -> f32 {
+    30.0
+}
+
+impl Default for SourceConfig {
+    fn default() -> Self {
+        // A GStreamer build is a Jetson build, even when nokhwa is compiled in too
+        if cfg!(feature = "csi") {
+            SourceConfig::Csi
+        } else {
+            SourceConfig::Camera { index: 0 }
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Result<Self, Error> {
+        let path = match env::var("VISIONCORE_CONFIG") {
+            Ok(path) => PathBuf::from(path),
+            Err(_) => return Ok(Config::default()),
+        };
+
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read config file {:?}", path))?;
+        let config: Config = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse config file {:?}", path))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Camera IDs end up in topic names, so they must be unique and free of '/'
+    fn validate(&self) -> Result<(), Error> {
+        self.detector.validate()?;
+        self.tracker.validate()?;
+        if let Some(head_pose) = &self.head_pose {
+            head_pose.validate()?;
+        }
+        if let Some(recognition) = &self.recognition {
+            recognition.validate()?;
+        }
+
+        let mut ids = HashSet::new();
+        for camera in &self.cameras {
+            if camera.id.is_empty() || camera.id.contains('/') {
+                return Err(anyhow!("Invalid camera id {:?}", camera.id));
+            }
+            if !ids.insert(camera.id.as_str()) {
+                return Err(anyhow!("Duplicate camera id {:?}", camera.id));
+            }
+            if let SourceConfig::Images { fps, .. } = camera.source {
+                if !fps.is_finite() || fps <= 0.0 {
+                    return Err(anyhow!("Camera {:?}: fps must be a positive number, got {}", camera.id, fps));
+                }
+            }
+            if let Some(detector) = &camera.detector {
+                detector.validate().with_context(|| format!("Camera {:?}", camera.id))?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+use std::ops::Deref;
+use std::sync::{Arc, Condvar, Mutex};
+
+use visioncore_plugin::{FrameRef, PixelFormat};
+
+// Pixel data of a frame. GStreamer buffers are read where the pipeline put
+// them instead of being copied out.
+#[derive(Clone)]
+pub enum FrameData {
+    Owned(Vec<u8>),
+    #[cfg(feature = "csi")]
+    Mapped(Arc<gstreamer::MappedBuffer<gstreamer::buffer::Readable>>),
+}
+
+impl Deref for FrameData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            FrameData::Owned(data) => data,
+            #[cfg(feature = "csi")]
+            FrameData::Mapped(buffer) => buffer.as_slice(),
+        }
+    }
+}
+
+impl From<Vec<u8>> for FrameData {
+    fn from(data: Vec<u8>) -> Self {
+        FrameData::Owned(data)
+    }
+}
+
+// A captured frame
+#[derive(Clone)]
+pub struct FrameBuffer {
+    pub camera: Arc<str>, // ID of the camera that captured the frame
+    pub data: FrameData, // Pixel data in `format`, as delivered by the source
+    pub width: u32,
+    pub height: u32,
+    pub format: PixelFormat,
+    pub stride: u32, // Bytes per row
+    pub seq: u64,          // Monotonically increasing per camera, assigned by the capture supervisor
+    pub timestamp_ns: u64, // Capture time on CLOCK_MONOTONIC, stream time for recordings
+}
+
+impl FrameBuffer {
+    pub fn new(data: impl Into<FrameData>, width: u32, height: u32, format: PixelFormat, stride: u32, timestamp_ns: u64) -> Self {
+        FrameBuffer {
+            camera: Arc::from(""),
+            data: data.into(),
+            width,
+            height,
+            format,
+            stride,
+            seq: 0,
+            timestamp_ns,
+        }
+    }
+
+    pub fn from_rgb(data: Vec<u8>, width: u32, height: u32, timestamp_ns: u64) -> Self {
+        FrameBuffer::new(data, width, height, PixelFormat::Rgb, width * 3, timestamp_ns)
+    }
+
+    // Borrow the frame for detection; the buffer stays alive for as long as
+    // the returned reference is in use
+    pub fn as_frame_ref(&self) -> FrameRef<'_> {
+        FrameRef::new(&self.data, self.width, self.height, self.format, self.stride)
+            .with_timing(self.seq, self.timestamp_ns)
+    }
+}
+
+// Single-frame handoff from the capture thread to the detection thread. A
+// new frame replaces one that has not been picked up yet, so detection always
+// runs on the newest frame, never sees a frame twice and sleeps while there
+// is nothing new.
+pub struct FrameSlot {
+    state: Mutex<SlotState>,
+    ready: Condvar,
+}
+
+struct SlotState {
+    frame: Option<FrameBuffer>,
+    closed: bool,
+    // Frames replaced before detection got to them
+    dropped: u64,
+}
+
+impl FrameSlot {
+    pub fn new() -> Self {
+        FrameSlot {
+            state: Mutex::new(SlotState {
+                frame: None,
+                closed: false,
+                dropped: 0,
+            }),
+            ready: Condvar::new(),
+        }
+    }
+
+    pub fn put(&self, frame: FrameBuffer) {
+        let mut state = self.state.lock().unwrap();
+        if state.frame.replace(frame).is_some() {
+            state.dropped += 1;
+        }
+        self.ready.notify_one();
+    }
+
+    // Discard a frame that has not been picked up yet
+    pub fn clear(&self) {
+        self.state.lock().unwrap().frame = None;
+    }
+
+    // Wake the consumer for good once capture has stopped
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+
+    // Block until a new frame arrives. Returns None once the slot is closed.
+    pub fn take(&self) -> Option<FrameBuffer> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(frame) = state.frame.take() {
+                return Some(frame);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.ready.wait(state).unwrap();
+        }
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.state.lock().unwrap().dropped
+    }
+}
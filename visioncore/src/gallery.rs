@@ -0,0 +1,63 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Error};
+use locinet::{FaceDetector, FaceRecognizer, Gallery};
+use visioncore_plugin::{FrameRef, PixelFormat};
+
+use crate::config::Config;
+
+const USAGE: &str = "Usage: visioncore gallery list | add <name> <image>... | remove <name>";
+
+// `visioncore gallery ...`: list, enroll and remove users of the face gallery
+// named by the `recognition` config (or the default gallery)
+pub fn run(config: &Config, args: &[String]) -> Result<(), Error> {
+    let recognition = config.recognition.clone().unwrap_or_default();
+
+    match args {
+        [command] if command == "list" => {
+            let gallery = Gallery::open(&recognition.gallery_path)?;
+            if gallery.entries().is_empty() {
+                println!("No users enrolled in {:?}", gallery.path());
+            }
+            for (name, samples) in gallery.list() {
+                println!("{} ({} samples)", name, samples);
+            }
+        }
+        [command, name, images @ ..] if command == "add" && !images.is_empty() => {
+            let mut detector = FaceDetector::new(config.detector.clone())
+                .context("Failed to load face detector")?;
+            let mut recognizer = FaceRecognizer::new(recognition)
+                .context("Failed to load face recognizer")?;
+            for image in images {
+                enroll(&mut detector, &mut recognizer, name, Path::new(image))?;
+                println!("Enrolled {:?} from {:?}", name, image);
+            }
+        }
+        [command, name] if command == "remove" => {
+            let mut gallery = Gallery::open(&recognition.gallery_path)?;
+            if !gallery.remove(name)? {
+                return Err(anyhow!("{:?} is not enrolled", name));
+            }
+            println!("Removed {:?}", name);
+        }
+        _ => return Err(anyhow!(USAGE)),
+    }
+    Ok(())
+}
+
+// Enroll the one face in an image file
+fn enroll(detector: &mut FaceDetector, recognizer: &mut FaceRecognizer, name: &str, path: &Path) -> Result<(), Error> {
+    let image = image::open(path)
+        .with_context(|| format!("Failed to decode {:?}", path))?
+        .to_rgb8();
+    let (width, height) = image.dimensions();
+    let frame = FrameRef::new(image.as_raw(), width, height, PixelFormat::Rgb, width * 3);
+
+    // Several faces would make it ambiguous who is being enrolled
+    let faces = detector.detect_faces(&frame)?;
+    let [face] = faces.as_slice() else {
+        return Err(anyhow!("Expected one face in {:?}, found {}", path, faces.len()));
+    };
+    recognizer.enroll(name, &frame, face)?;
+    Ok(())
+}
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use anyhow::Error;
+use locinet::{FaceDetector, FaceRecognizer, FaceTracker, HeadPose, HeadPoseEstimator, Identity, ModelInfo, TrackState, TrackedFace};
+use serde::Serialize;
+use visioncore_plugin::{convert, FrameRef, PixelFormat};
+
+use crate::capture::CaptureSupervisor;
+use crate::config::CameraConfig;
+use crate::frame::FrameSlot;
+use crate::publisher::Publisher;
+
+// Published on `<prefix>/<camera>/face_position`
+#[derive(Serialize)]
+struct FaceMessage<'a> {
+    camera: &'a str,
+    frame_id: u64,
+    // Capture time on CLOCK_MONOTONIC, for latency compensation
+    capture_ts_ns: u64,
+    // track_id, state and age, followed by the face fields
+    #[serde(flatten)]
+    face: &'a TrackedFace,
+    // None for lost faces, when head pose is disabled or the fit failed
+    head_pose: Option<HeadPose>,
+    // `identity` and `similarity`; left out for lost faces and without recognition
+    #[serde(flatten)]
+    identity: Option<Identity>,
+}
+
+// Published once on `<prefix>/<camera>/model_info` when the pipeline starts
+#[derive(Serialize)]
+struct ModelInfoMessage<'a> {
+    camera: &'a str,
+    #[serde(flatten)]
+    model: &'a ModelInfo,
+}
+
+// Capture and detection threads for one camera
+pub struct CameraPipeline {
+    pub id: Arc<str>,
+    capture: JoinHandle<()>,
+    detection: JoinHandle<()>,
+}
+
+impl CameraPipeline {
+    pub fn spawn(
+        camera: &CameraConfig,
+        detector: FaceDetector,
+        tracker: FaceTracker,
+        head_pose: Option<HeadPoseEstimator>,
+        recognizer: Option<FaceRecognizer>,
+        publisher: Arc<Publisher>,
+    ) -> Result<Self, Error> {
+        let id: Arc<str> = Arc::from(camera.id.as_str());
+        report_model(&id, detector.model_info(), &publisher);
+
+        // Hands frames from the capture thread to the detection thread
+        let frame_slot = Arc::new(FrameSlot::new());
+
+        // The source is built inside the capture thread because camera
+        // handles are not necessarily Send.
+        let capture = {
+            let mut supervisor = CaptureSupervisor::new(
+                Arc::clone(&id),
+                camera.source.clone(),
+                camera.reconnect.clone(),
+                Arc::clone(&frame_slot),
+                Arc::clone(&publisher),
+            );
+            let id = Arc::clone(&id);
+            let frame_slot = Arc::clone(&frame_slot);
+            thread::Builder::new()
+                .name(format!("capture-{}", id))
+                .spawn(move || {
+                    if let Err(e) = supervisor.run() {
+                        eprintln!("[{}] Capture error: {:?}", id, e);
+                    }
+                    // Let the detection thread finish
+                    frame_slot.close();
+                })?
+        };
+
+        let detection = {
+            let id = Arc::clone(&id);
+            thread::Builder::new()
+                .name(format!("detect-{}", id))
+                .spawn(move || {
+                    if let Err(e) = run_detection(detector, tracker, head_pose, recognizer, &frame_slot, &publisher) {
+                        eprintln!("[{}] Detection error: {:?}", id, e);
+                    }
+                    println!("[{}] Detection stopped, {} frames skipped", id, frame_slot.dropped());
+                })?
+        };
+
+        Ok(CameraPipeline { id, capture, detection })
+    }
+
+    pub fn join(self) {
+        let _ = self.capture.join();
+        let _ = self.detection.join();
+    }
+}
+
+fn report_model(camera: &str, model: &ModelInfo, publisher: &Publisher) {
+    println!(
+        "[{}] Face detector {:?}: {}x{} input, {} anchors, outputs {:?}",
+        camera,
+        model.path,
+        model.input_size,
+        model.input_size,
+        model.num_anchors.unwrap_or(0),
+        model.outputs.iter().map(|o| &o.shape).collect::<Vec<_>>(),
+    );
+    let message = ModelInfoMessage { camera, model };
+    if let Err(e) = publisher.publish(camera, "model_info", &message) {
+        eprintln!("[{}] Failed to publish model info: {:?}", camera, e);
+    }
+}
+
+// Identity of a track, reused until the track is due to be identified again
+struct CachedIdentity {
+    identity: Identity,
+    // Frames since the embedding model last ran on the track
+    frames: u32,
+}
+
+// Run face detection, tracking, head pose estimation and recognition on each
+// new frame of the camera and publish the results
+fn run_detection(
+    mut detector: FaceDetector,
+    mut tracker: FaceTracker,
+    mut head_pose: Option<HeadPoseEstimator>,
+    mut recognizer: Option<FaceRecognizer>,
+    frame_slot: &FrameSlot,
+    publisher: &Publisher,
+) -> Result<(), Error> {
+    // Reused for frames that locinet cannot read natively
+    let mut converted = Vec::new();
+    // Identities by track ID; the embedding model runs only on new tracks,
+    // every `refresh_frames` frames and after the gallery changed
+    let mut identities: HashMap<u64, CachedIdentity> = HashMap::new();
+
+    while let Some(buffer) = frame_slot.take() {
+        let mut frame = buffer.as_frame_ref();
+        if !locinet::ACCEPTED_FORMATS.contains(&frame.format) {
+            let stride = match convert::convert_into(&frame, PixelFormat::Rgb, &mut converted) {
+                Ok(stride) => stride,
+                Err(e) => {
+                    eprintln!("[{}] Dropping frame {}: {}", buffer.camera, buffer.seq, e);
+                    continue;
+                }
+            };
+            frame = FrameRef::new(&converted, frame.width, frame.height, PixelFormat::Rgb, stride)
+                .with_timing(frame.seq, frame.timestamp_ns);
+        }
+
+        // A frame the detector cannot handle is dropped; the next one may be fine
+        let faces = match detector.detect_faces(&frame) {
+            Ok(faces) => faces,
+            Err(e) => {
+                eprintln!("[{}] Detection failed on frame {}: {}", buffer.camera, buffer.seq, e);
+                continue;
+            }
+        };
+
+        // Pick up users enrolled with `visioncore gallery add` while running;
+        // every face is matched against the new gallery
+        if let Some(recognizer) = &mut recognizer {
+            match recognizer.gallery_mut().reload_if_changed() {
+                Ok(true) => identities.clear(),
+                Ok(false) => (),
+                Err(e) => eprintln!("[{}] {}", buffer.camera, e),
+            }
+        }
+
+        // Lost tracks are published too, at their predicted position
+        let tracked = tracker.update(&faces, buffer.timestamp_ns);
+        identities.retain(|track_id, _| tracked.iter().any(|face| face.track_id == *track_id));
+        for face in &tracked {
+            println!(
+                "[{}] Face {} ({:?}): {:?} | Score: {:?} | Center: {:?}",
+                buffer.camera, face.track_id, face.state, face.face.bbox, face.face.score, face.face.center
+            );
+            // A lost face is not in this frame, so there is nothing to measure
+            let pose = match &mut head_pose {
+                Some(estimator) if face.state != TrackState::Lost => {
+                    estimator.estimate(&frame, &face.face).unwrap_or_else(|e| {
+                        eprintln!("[{}] Head pose failed on frame {}: {}", buffer.camera, buffer.seq, e);
+                        None
+                    })
+                }
+                _ => None,
+            };
+            let identity = match &mut recognizer {
+                Some(recognizer) if face.state != TrackState::Lost => {
+                    let refresh_frames = recognizer.config().refresh_frames;
+                    match identities.get_mut(&face.track_id) {
+                        Some(cached) if cached.frames < refresh_frames => {
+                            cached.frames += 1;
+                            Some(cached.identity.clone())
+                        }
+                        _ => match recognizer.identify(&frame, &face.face) {
+                            Ok(identity) => {
+                                identities.insert(face.track_id, CachedIdentity { identity: identity.clone(), frames: 1 });
+                                Some(identity)
+                            }
+                            // Keep the last identity, if any, and try again on the next frame
+                            Err(e) => {
+                                eprintln!("[{}] Recognition failed on frame {}: {}", buffer.camera, buffer.seq, e);
+                                identities.get(&face.track_id).map(|cached| cached.identity.clone())
+                            }
+                        },
+                    }
+                }
+                _ => None,
+            };
+            let message = FaceMessage {
+                camera: &buffer.camera,
+                frame_id: buffer.seq,
+                capture_ts_ns: buffer.timestamp_ns,
+                face,
+                head_pose: pose,
+                identity,
+            };
+            publisher.publish(&buffer.camera, "face_position", &message)?;
+        }
+    }
+
+    Ok(())
+}
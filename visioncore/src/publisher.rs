@@ -0,0 +1,33 @@
+use std::sync::Mutex;
+
+use anyhow::Error;
+use serde::Serialize;
+use zmq::{Context, Socket, SocketType};
+
+// ZeroMQ publisher shared by all camera pipelines. Messages go out on
+// `<prefix>/<camera>/<topic>` as JSON.
+pub struct Publisher {
+    socket: Mutex<Socket>,
+    prefix: String,
+}
+
+impl Publisher {
+    pub fn connect(context: &Context, endpoint: &str, prefix: &str) -> Result<Self, Error> {
+        let socket = context.socket(SocketType::PUB)?;
+        socket.connect(endpoint)?;
+        Ok(Publisher {
+            socket: Mutex::new(socket),
+            prefix: prefix.to_string(),
+        })
+    }
+
+    pub fn publish<T: Serialize>(&self, camera: &str, topic: &str, message: &T) -> Result<(), Error> {
+        let topic = format!("{}/{}/{}", self.prefix, camera, topic);
+        // Serialize the message to a JSON string
+        let data = serde_json::to_string(message)?;
+
+        let socket = self.socket.lock().unwrap();
+        socket.send_multipart([topic.as_bytes(), data.as_bytes()], 0)?;
+        Ok(())
+    }
+}
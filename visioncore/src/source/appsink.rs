@@ -0,0 +1,362 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Error};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use gstreamer_app::AppSink;
+use gstreamer_video as gst_video;
+use visioncore_plugin::PixelFormat;
+
+use super::{first_frame_timeout, FrameSource, SourceCaps};
+use crate::clock::monotonic_now_ns;
+use crate::frame::{FrameBuffer, FrameData};
+
+// Capture from the Jetson CSI camera, rotated to portrait
+pub const CSI_PIPELINE: &str = "nvarguscamerasrc ! video/x-raw(memory:NVMM),width=1920,height=1080,framerate=30/1 ! nvvidconv flip-method=3 ! video/x-raw,width=1080,height=1920,format=RGBA ! appsink name=sink";
+
+// Decode a video file to RGBA. With `sync` the appsink plays at the file's
+// native rate, otherwise frames are delivered as fast as they decode.
+pub fn video_pipeline(path: &Path, sync: bool) -> String {
+    let location = path.to_string_lossy().replace('"', "\\\"");
+    format!(
+        "filesrc location=\"{}\" ! decodebin ! videoconvert ! video/x-raw,format=RGBA ! appsink name=sink sync={} max-buffers=2",
+        location, sync
+    )
+}
+
+// Raw video formats the appsink may negotiate. Frames are passed on in
+// their native layout; conversion happens only if the detector needs it.
+const GST_FORMATS: [(&str, PixelFormat); 10] = [
+    ("RGB", PixelFormat::Rgb),
+    ("BGR", PixelFormat::Bgr),
+    ("RGBA", PixelFormat::Rgba),
+    ("RGBx", PixelFormat::Rgba),
+    ("BGRA", PixelFormat::Bgra),
+    ("BGRx", PixelFormat::Bgra),
+    ("NV12", PixelFormat::Nv12),
+    ("YUY2", PixelFormat::Yuyv),
+    ("YUYV", PixelFormat::Yuyv),
+    ("GRAY8", PixelFormat::Gray),
+];
+
+fn pixel_format(structure: &gst::StructureRef) -> Result<PixelFormat, Error> {
+    let name = structure
+        .get::<&str>("format")
+        .map_err(|_| anyhow!("appsink caps have no pixel format"))?;
+    GST_FORMATS
+        .iter()
+        .find(|(gst_name, _)| *gst_name == name)
+        .map(|(_, format)| *format)
+        .ok_or_else(|| {
+            let supported: Vec<&str> = GST_FORMATS.iter().map(|(gst_name, _)| *gst_name).collect();
+            anyhow!(
+                "appsink negotiated unsupported format {}; supported formats are {:?} (add `videoconvert ! video/x-raw,format=RGBA` before the appsink)",
+                name,
+                supported
+            )
+        })
+}
+
+// Row strides and plane offsets of a buffer: from its VideoMeta when the
+// producer attached one (hardware converters pad rows their own way),
+// otherwise GStreamer's default layout for the caps
+fn plane_layout(buffer: &gst::BufferRef, info: &gst_video::VideoInfo) -> Result<(Vec<usize>, Vec<usize>), Error> {
+    let (strides, offsets) = match buffer.meta::<gst_video::VideoMeta>() {
+        Some(meta) => (meta.stride().to_vec(), meta.offset().to_vec()),
+        None => (info.stride().to_vec(), info.offset().to_vec()),
+    };
+    let strides = strides
+        .iter()
+        .map(|&stride| usize::try_from(stride).map_err(|_| anyhow!("Negative row stride {} is not supported", stride)))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((strides, offsets))
+}
+
+// Copy the planes of a frame into the layout `FrameRef` describes: the first
+// plane at the start of the buffer and the NV12 UV plane right after it, with
+// one stride for both. Returns the data and its stride.
+fn repack(
+    data: &[u8],
+    format: PixelFormat,
+    width: u32,
+    height: u32,
+    strides: &[usize],
+    offsets: &[usize],
+) -> Result<(Vec<u8>, u32), Error> {
+    // (rows, bytes per row) of each plane
+    let planes = match format {
+        PixelFormat::Nv12 => vec![
+            (height as usize, width as usize),
+            (height.div_ceil(2) as usize, format.min_stride(width)),
+        ],
+        _ => vec![(height as usize, format.min_stride(width))],
+    };
+    if strides.len() < planes.len() || offsets.len() < planes.len() {
+        return Err(anyhow!("{:?} buffer has {} planes, expected {}", format, strides.len().min(offsets.len()), planes.len()));
+    }
+
+    let stride = planes.iter().map(|&(_, row)| row).max().unwrap_or(0);
+    let mut out = Vec::with_capacity(format.buffer_len(stride, height));
+    for (plane, &(rows, row_len)) in planes.iter().enumerate() {
+        for row in 0..rows {
+            let start = offsets[plane] + row * strides[plane];
+            let bytes = data
+                .get(start..start + row_len)
+                .ok_or_else(|| anyhow!("{:?} buffer of {} bytes is too short for {}x{}", format, data.len(), width, height))?;
+            out.extend_from_slice(bytes);
+            out.resize(out.len() + stride - row_len, 0);
+        }
+    }
+    Ok((out, stride as u32))
+}
+
+// Frames pulled from the `sink` appsink of a GStreamer pipeline. The pipeline
+// description is free-form as long as it ends in an appsink named `sink`
+// producing raw video in system memory.
+pub struct AppSinkSource {
+    description: String,
+    pipeline: Option<gst::Pipeline>,
+    appsink: Option<AppSink>,
+    format: Option<PixelFormat>,
+    stall_timeout: Option<Duration>,
+    // First sample, pulled during open to learn the caps
+    pending: Option<gst::Sample>,
+    // Set for recorded streams, which are stamped with their own timeline
+    stream_time: Option<StreamTime>,
+}
+
+// Timeline of a recorded stream: the PTS relative to the first frame. A
+// looping replay continues where the previous pass ended, so timestamps
+// keep increasing.
+#[derive(Default)]
+struct StreamTime {
+    first_pts: Option<gst::ClockTime>,
+    // Timestamp of the first frame of the current pass
+    start_ns: u64,
+    // Earliest timestamp the frame after the last one may have
+    next_ns: u64,
+}
+
+impl StreamTime {
+    fn stamp(&mut self, buffer: &gst::BufferRef) -> u64 {
+        let timestamp_ns = match (buffer.pts(), self.first_pts) {
+            (Some(pts), Some(first_pts)) => self.start_ns + pts.saturating_sub(first_pts).nseconds(),
+            (Some(pts), None) => {
+                self.first_pts = Some(pts);
+                self.start_ns
+            }
+            (None, _) => self.next_ns,
+        };
+        let duration_ns = buffer.duration().map(|d| d.nseconds()).filter(|&d| d > 0).unwrap_or(1);
+        self.next_ns = self.next_ns.max(timestamp_ns + duration_ns);
+        timestamp_ns
+    }
+
+    // Start a new pass after the last frame of the previous one
+    fn restart(&mut self) {
+        self.first_pts = None;
+        self.start_ns = self.next_ns;
+    }
+}
+
+impl AppSinkSource {
+    pub fn new(description: &str) -> Self {
+        AppSinkSource {
+            description: description.to_string(),
+            pipeline: None,
+            appsink: None,
+            format: None,
+            stall_timeout: None,
+            pending: None,
+            stream_time: None,
+        }
+    }
+
+    // Frames of a recorded stream. Their timestamps are the PTS relative to
+    // the first frame rather than CLOCK_MONOTONIC, since a replay that
+    // decodes faster than real time would otherwise be stamped in the future.
+    pub fn replay(description: &str) -> Self {
+        AppSinkSource {
+            stream_time: Some(StreamTime::default()),
+            ..AppSinkSource::new(description)
+        }
+    }
+
+    fn pull(&self, appsink: &AppSink, timeout: Option<Duration>) -> Result<Option<gst::Sample>, Error> {
+        let gst_timeout = timeout.map(|t| gst::ClockTime::from_mseconds(t.as_millis() as u64));
+        match appsink.try_pull_sample(gst_timeout) {
+            Some(sample) => Ok(Some(sample)),
+            None if appsink.is_eos() => Ok(None),
+            None => Err(self.bus_error().unwrap_or_else(|| match timeout {
+                Some(timeout) => anyhow!("No frame received for {:?}", timeout),
+                None => anyhow!("appsink stopped delivering samples"),
+            })),
+        }
+    }
+
+    // Capture time of a sample on CLOCK_MONOTONIC, falling back to the time
+    // it was pulled when the PTS cannot be mapped. Recorded streams use their
+    // own timeline instead.
+    fn capture_timestamp(&mut self, sample: &gst::Sample) -> u64 {
+        if let (Some(stream_time), Some(buffer)) = (self.stream_time.as_mut(), sample.buffer()) {
+            return stream_time.stamp(buffer);
+        }
+        self.pts_to_monotonic(sample).unwrap_or_else(monotonic_now_ns)
+    }
+
+    // With GStreamer's default system clock, base time + running time of the
+    // buffer PTS is the capture time on the monotonic clock
+    fn pts_to_monotonic(&self, sample: &gst::Sample) -> Option<u64> {
+        let pipeline = self.pipeline.as_ref()?;
+        let clock = pipeline.clock()?;
+        let system_clock = clock.downcast_ref::<gst::SystemClock>()?;
+        if system_clock.property::<gst::ClockType>("clock-type") != gst::ClockType::Monotonic {
+            return None;
+        }
+
+        let pts = sample.buffer()?.pts()?;
+        let segment = sample.segment()?.downcast_ref::<gst::ClockTime>()?;
+        let running_time = segment.to_running_time(pts)?;
+        Some((pipeline.base_time()? + running_time).nseconds())
+    }
+
+    // Turn a pipeline error posted on the bus into an anyhow error
+    fn bus_error(&self) -> Option<Error> {
+        let bus = self.pipeline.as_ref()?.bus()?;
+        let msg = bus.pop_filtered(&[gst::MessageType::Error])?;
+        match msg.view() {
+            gst::MessageView::Error(err) => Some(anyhow!(
+                "Pipeline error from {:?}: {} ({:?})",
+                err.src().map(|s| s.path_string()),
+                err.error(),
+                err.debug()
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl FrameSource for AppSinkSource {
+    fn open(&mut self) -> Result<SourceCaps, Error> {
+        gst::init()?;
+
+        let pipeline = gst::parse::launch(&self.description)
+            .map_err(|e| anyhow!("Invalid pipeline {:?}: {}", self.description, e))?
+            .downcast::<gst::Pipeline>()
+            .map_err(|_| anyhow!("Expected a Pipeline"))?;
+
+        let appsink = pipeline
+            .by_name("sink")
+            .ok_or_else(|| anyhow!("Pipeline has no element named `sink`"))?
+            .downcast::<AppSink>()
+            .map_err(|_| anyhow!("Sink is not an AppSink"))?;
+
+        pipeline.set_state(gst::State::Playing)?;
+        self.pipeline = Some(pipeline);
+
+        // The caps are only known once the first sample arrives, which for
+        // network and camera sources takes a while after going to Playing
+        let sample = self
+            .pull(&appsink, first_frame_timeout(self.stall_timeout))?
+            .ok_or_else(|| anyhow!("Stream ended before the first frame"))?;
+        let format = sample_format(&sample)?;
+        let (width, height, fps) = sample_caps(&sample)?;
+        println!("appsink negotiated {:?} {}x{} @ {:?} fps", format, width, height, fps);
+
+        let caps = SourceCaps { width, height, fps };
+        self.appsink = Some(appsink);
+        self.format = Some(format);
+        self.pending = Some(sample);
+        Ok(caps)
+    }
+
+    fn next_frame(&mut self) -> Result<Option<FrameBuffer>, Error> {
+        let sample = match self.pending.take() {
+            Some(sample) => sample,
+            None => {
+                let appsink = self.appsink.as_ref().ok_or_else(|| anyhow!("Pipeline is not open"))?;
+                match self.pull(appsink, self.stall_timeout)? {
+                    Some(sample) => sample,
+                    None => return Ok(None),
+                }
+            }
+        };
+
+        let format = self.format.ok_or_else(|| anyhow!("Pipeline is not open"))?;
+        let caps = sample.caps().ok_or_else(|| anyhow!("Sample has no caps"))?;
+        let info = gst_video::VideoInfo::from_caps(caps).map_err(|e| anyhow!("Cannot read video info from caps: {}", e))?;
+        let (width, height) = (info.width(), info.height());
+        let timestamp_ns = self.capture_timestamp(&sample);
+        let buffer = sample.buffer_owned().ok_or_else(|| anyhow!("Sample has no buffer"))?;
+        let (strides, offsets) = plane_layout(&buffer, &info)?;
+
+        // Hand the mapped buffer on as is when its layout is one `FrameRef`
+        // can describe, which is the common case; repack it otherwise (e.g.
+        // NV12 with an odd height, where the UV plane starts on an even row)
+        let stride = *strides.first().ok_or_else(|| anyhow!("Buffer has no planes"))?;
+        let in_place = offsets[0] == 0
+            && (format != PixelFormat::Nv12
+                || (strides.get(1) == Some(&stride) && offsets.get(1) == Some(&(stride * height as usize))));
+        let frame = if in_place {
+            let map = buffer
+                .into_mapped_buffer_readable()
+                .map_err(|_| anyhow!("Failed to map buffer"))?;
+            FrameBuffer::new(FrameData::Mapped(Arc::new(map)), width, height, format, stride as u32, timestamp_ns)
+        } else {
+            let map = buffer.map_readable()?;
+            let (data, stride) = repack(map.as_slice(), format, width, height, &strides, &offsets)?;
+            FrameBuffer::new(data, width, height, format, stride, timestamp_ns)
+        };
+        frame.as_frame_ref().validate().map_err(|e| anyhow!(e))?;
+        Ok(Some(frame))
+    }
+
+    fn set_stall_timeout(&mut self, timeout: Duration) {
+        self.stall_timeout = Some(timeout);
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        self.pending = None;
+        self.format = None;
+        if let Some(stream_time) = self.stream_time.as_mut() {
+            stream_time.restart();
+        }
+        self.appsink = None;
+        if let Some(pipeline) = self.pipeline.take() {
+            pipeline.set_state(gst::State::Null)?;
+        }
+        Ok(())
+    }
+}
+
+// Width, height and framerate of a sample
+fn sample_caps(sample: &gst::Sample) -> Result<(u32, u32, Option<f32>), Error> {
+    let caps = sample.caps().ok_or_else(|| anyhow!("Sample has no caps"))?;
+    let structure = caps.structure(0).ok_or_else(|| anyhow!("Caps have no structure"))?;
+    let width = structure.get::<i32>("width")? as u32;
+    let height = structure.get::<i32>("height")? as u32;
+    let fps = structure
+        .get::<gst::Fraction>("framerate")
+        .ok()
+        .filter(|f| f.numer() > 0 && f.denom() > 0)
+        .map(|f| f.numer() as f32 / f.denom() as f32);
+    Ok((width, height, fps))
+}
+
+// Check that the appsink receives raw video in system memory in a format
+// VisionCore understands
+fn sample_format(sample: &gst::Sample) -> Result<PixelFormat, Error> {
+    let caps = sample.caps().ok_or_else(|| anyhow!("Sample has no caps"))?;
+    let structure = caps.structure(0).ok_or_else(|| anyhow!("Caps have no structure"))?;
+    if !structure.has_name("video/x-raw") {
+        return Err(anyhow!("appsink must receive video/x-raw, got {}", structure.name()));
+    }
+    if let Some(features) = caps.features(0) {
+        if features.contains("memory:NVMM") {
+            return Err(anyhow!("appsink cannot map NVMM buffers; add `nvvidconv` before the appsink"));
+        }
+    }
+    pixel_format(structure)
+}
@@ -0,0 +1,127 @@
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, Error};
+use nokhwa::pixel_format::RgbFormat;
+use nokhwa::utils::{CameraIndex, RequestedFormat, RequestedFormatType};
+use nokhwa::Camera;
+
+use super::{first_frame_timeout, FrameSource, SourceCaps};
+use crate::clock::monotonic_now_ns;
+use crate::frame::FrameBuffer;
+
+// USB / V4L2 camera captured through nokhwa. nokhwa reads block with no
+// timeout, so the camera lives on a worker thread that captures a frame per
+// request; a request that is not answered within the stall timeout fails.
+pub struct CameraSource {
+    index: u32,
+    worker: Option<Worker>,
+    stall_timeout: Option<Duration>,
+}
+
+struct Worker {
+    requests: Sender<()>,
+    frames: Receiver<Result<FrameBuffer, Error>>,
+}
+
+impl CameraSource {
+    pub fn new(index: u32) -> Self {
+        CameraSource {
+            index,
+            worker: None,
+            stall_timeout: None,
+        }
+    }
+}
+
+// Wait for the worker's answer, for at most `timeout` if one is set
+fn receive<T>(receiver: &Receiver<T>, timeout: Option<Duration>) -> Result<T, Error> {
+    match timeout {
+        Some(timeout) => receiver.recv_timeout(timeout).map_err(|e| match e {
+            RecvTimeoutError::Timeout => anyhow!("No frame received for {:?}", timeout),
+            RecvTimeoutError::Disconnected => anyhow!("Camera worker exited"),
+        }),
+        None => receiver.recv().map_err(|_| anyhow!("Camera worker exited")),
+    }
+}
+
+fn open_camera(index: u32) -> Result<(Camera, SourceCaps), Error> {
+    let requested = RequestedFormat::new::<RgbFormat>(RequestedFormatType::None);
+    let mut camera = Camera::new(CameraIndex::Index(index), requested)?;
+    camera.open_stream()?;
+
+    let resolution = camera.resolution();
+    let caps = SourceCaps {
+        width: resolution.width(),
+        height: resolution.height(),
+        fps: Some(camera.frame_rate() as f32),
+    };
+    Ok((camera, caps))
+}
+
+fn read_frame(camera: &mut Camera) -> Result<FrameBuffer, Error> {
+    let frame = camera.frame()?;
+    let timestamp_ns = monotonic_now_ns();
+    let rgb_data = frame.decode_image::<RgbFormat>()?;
+
+    let width = rgb_data.width();
+    let height = rgb_data.height();
+    Ok(FrameBuffer::from_rgb(rgb_data.into_raw(), width, height, timestamp_ns))
+}
+
+impl FrameSource for CameraSource {
+    fn open(&mut self) -> Result<SourceCaps, Error> {
+        let index = self.index;
+        let (caps_tx, caps_rx) = mpsc::channel();
+        let (request_tx, request_rx) = mpsc::channel::<()>();
+        let (frame_tx, frame_rx) = mpsc::channel();
+
+        thread::Builder::new()
+            .name(format!("camera-{}", index))
+            .spawn(move || {
+                let mut camera = match open_camera(index) {
+                    Ok((camera, caps)) => {
+                        let _ = caps_tx.send(Ok(caps));
+                        camera
+                    }
+                    Err(e) => {
+                        let _ = caps_tx.send(Err(e));
+                        return;
+                    }
+                };
+
+                // Serve requests until the source is closed. A worker stuck
+                // in a read is left behind and exits once the read returns.
+                while request_rx.recv().is_ok() {
+                    if frame_tx.send(read_frame(&mut camera)).is_err() {
+                        break;
+                    }
+                }
+                if let Err(e) = camera.stop_stream() {
+                    eprintln!("Failed to stop camera {}: {:?}", index, e);
+                }
+            })?;
+
+        let caps = receive(&caps_rx, first_frame_timeout(self.stall_timeout))??;
+        self.worker = Some(Worker { requests: request_tx, frames: frame_rx });
+        Ok(caps)
+    }
+
+    fn next_frame(&mut self) -> Result<Option<FrameBuffer>, Error> {
+        let worker = self.worker.as_ref().ok_or_else(|| anyhow!("Camera is not open"))?;
+        worker.requests.send(()).map_err(|_| anyhow!("Camera worker exited"))?;
+        let frame = receive(&worker.frames, self.stall_timeout)??;
+        Ok(Some(frame))
+    }
+
+    fn set_stall_timeout(&mut self, timeout: Duration) {
+        self.stall_timeout = Some(timeout);
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        // Dropping the request channel stops the worker, which stops the stream
+        self.worker = None;
+        Ok(())
+    }
+}
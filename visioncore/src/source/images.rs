@@ -0,0 +1,106 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Error};
+
+use super::{FrameSource, SourceCaps};
+use crate::frame::FrameBuffer;
+
+const IMAGE_EXTENSIONS: [&str; 3] = ["png", "jpg", "jpeg"];
+
+// Frames decoded from the PNG/JPEG files of a directory, in file name order
+pub struct ImageDirSource {
+    dir: PathBuf,
+    fps: f32,
+    files: Vec<PathBuf>,
+    position: usize,
+    // First image, decoded by `open` to learn the frame size
+    first: Option<FrameBuffer>,
+    // Frames are stamped at `fps` from the start of the replay. A looping
+    // replay continues where the previous pass ended, so timestamps keep
+    // increasing.
+    start_ns: u64,
+    next_ns: u64,
+}
+
+impl ImageDirSource {
+    pub fn new(dir: &Path, fps: f32) -> Self {
+        ImageDirSource {
+            dir: dir.to_path_buf(),
+            fps,
+            files: Vec::new(),
+            position: 0,
+            first: None,
+            start_ns: 0,
+            next_ns: 0,
+        }
+    }
+
+    // Timestamp of the frame at `position` in the current pass
+    fn stamp(&mut self, frame: &mut FrameBuffer, position: usize) {
+        let period_ns = 1e9 / self.fps as f64;
+        frame.timestamp_ns = self.start_ns + (position as f64 * period_ns) as u64;
+        self.next_ns = self.start_ns + ((position + 1) as f64 * period_ns) as u64;
+    }
+
+    fn decode(path: &Path) -> Result<FrameBuffer, Error> {
+        let image = image::open(path)
+            .with_context(|| format!("Failed to decode {:?}", path))?
+            .to_rgb8();
+        let (width, height) = image.dimensions();
+        Ok(FrameBuffer::from_rgb(image.into_raw(), width, height, 0))
+    }
+}
+
+impl FrameSource for ImageDirSource {
+    fn open(&mut self) -> Result<SourceCaps, Error> {
+        let mut files: Vec<PathBuf> = fs::read_dir(&self.dir)
+            .with_context(|| format!("Failed to read image directory {:?}", self.dir))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| {
+                path.extension()
+                    .and_then(|ext| ext.to_str())
+                    .map(|ext| IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+                    .unwrap_or(false)
+            })
+            .collect();
+        files.sort();
+
+        let first = files
+            .first()
+            .ok_or_else(|| anyhow!("No PNG or JPEG files in {:?}", self.dir))?;
+        let frame = Self::decode(first)?;
+
+        let caps = SourceCaps {
+            width: frame.width,
+            height: frame.height,
+            fps: Some(self.fps),
+        };
+        self.files = files;
+        self.position = 1;
+        self.first = Some(frame);
+        Ok(caps)
+    }
+
+    fn next_frame(&mut self) -> Result<Option<FrameBuffer>, Error> {
+        if let Some(mut frame) = self.first.take() {
+            self.stamp(&mut frame, 0);
+            return Ok(Some(frame));
+        }
+        let Some(path) = self.files.get(self.position) else {
+            return Ok(None);
+        };
+        let mut frame = Self::decode(path)?;
+        self.stamp(&mut frame, self.position);
+        self.position += 1;
+        Ok(Some(frame))
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        self.files.clear();
+        self.position = 0;
+        self.first = None;
+        self.start_ns = self.next_ns;
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+#[cfg(feature = "nokhwa")]
+mod camera;
+#[cfg(feature = "csi")]
+mod appsink;
+mod images;
+mod replay;
+
+use std::time::Duration;
+
+use anyhow::Error;
+
+use crate::config::SourceConfig;
+#[cfg(feature = "csi")]
+use crate::config::Playback;
+use crate::frame::FrameBuffer;
+use images::ImageDirSource;
+use replay::ReplaySource;
+
+// What a source actually delivers once it is open
+#[derive(Debug, Clone, Copy)]
+pub struct SourceCaps {
+    pub width: u32,
+    pub height: u32,
+    pub fps: Option<f32>,
+}
+
+/// A backend that produces frames for the inference loop, in any of the
+/// `PixelFormat`s a `FrameBuffer` can carry.
+///
+/// Sources are created and driven from the capture thread, so they do not
+/// need to be `Send`.
+pub trait FrameSource {
+    /// Starts streaming and returns the negotiated caps.
+    fn open(&mut self) -> Result<SourceCaps, Error>;
+
+    /// Blocks until the next frame is available. `Ok(None)` means the
+    /// source reached the end of its stream.
+    fn next_frame(&mut self) -> Result<Option<FrameBuffer>, Error>;
+
+    /// Makes `next_frame` fail instead of blocking when no frame arrives
+    /// within `timeout`. Sources that cannot time out a read ignore it and
+    /// rely on the device reporting an error.
+    fn set_stall_timeout(&mut self, _timeout: Duration) {}
+
+    /// Stops streaming and releases the device.
+    fn close(&mut self) -> Result<(), Error>;
+}
+
+// Sources take longer to deliver their first frame than the ones after it
+// (device setup, RTSP negotiation, decoder start), so it gets a longer timeout
+#[cfg(any(feature = "nokhwa", feature = "csi"))]
+const MIN_FIRST_FRAME_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[cfg(any(feature = "nokhwa", feature = "csi"))]
+fn first_frame_timeout(stall_timeout: Option<Duration>) -> Option<Duration> {
+    stall_timeout.map(|timeout| timeout.max(MIN_FIRST_FRAME_TIMEOUT))
+}
+
+// Build the source selected in the config
+pub fn from_config(config: &SourceConfig) -> Result<Box<dyn FrameSource>, Error> {
+    match config {
+        #[cfg(feature = "nokhwa")]
+        SourceConfig::Camera { index } => Ok(Box::new(camera::CameraSource::new(*index))),
+        #[cfg(not(feature = "nokhwa"))]
+        SourceConfig::Camera { index } => Err(anyhow::anyhow!("Camera source {} requires the `nokhwa` feature", index)),
+
+        #[cfg(feature = "csi")]
+        SourceConfig::Csi => Ok(Box::new(appsink::AppSinkSource::new(appsink::CSI_PIPELINE))),
+        #[cfg(not(feature = "csi"))]
+        SourceConfig::Csi => Err(anyhow::anyhow!("CSI source requires the `csi` feature")),
+
+        #[cfg(feature = "csi")]
+        SourceConfig::Gstreamer { pipeline } => Ok(Box::new(appsink::AppSinkSource::new(pipeline))),
+        #[cfg(not(feature = "csi"))]
+        SourceConfig::Gstreamer { pipeline } => {
+            Err(anyhow::anyhow!("GStreamer source {:?} requires the `csi` feature", pipeline))
+        }
+
+        #[cfg(feature = "csi")]
+        SourceConfig::Video { path, playback, looping } => {
+            // Native playback is paced by the appsink clock, the others pull as fast as they can
+            let sync = *playback == Playback::Native;
+            let description = appsink::video_pipeline(path, sync);
+            let video = Box::new(appsink::AppSinkSource::replay(&description));
+            Ok(Box::new(ReplaySource::new(video, *playback, None, *looping)))
+        }
+        #[cfg(not(feature = "csi"))]
+        SourceConfig::Video { path, playback, looping } => Err(anyhow::anyhow!(
+            "Video source {:?} ({:?} playback, looping {}) requires the `csi` feature",
+            path,
+            playback,
+            looping
+        )),
+
+        SourceConfig::Images { path, fps, playback, looping } => {
+            let images = Box::new(ImageDirSource::new(path, *fps));
+            Ok(Box::new(ReplaySource::new(images, *playback, Some(*fps), *looping)))
+        }
+    }
+}
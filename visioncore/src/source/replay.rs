@@ -0,0 +1,82 @@
+use std::io::{self, BufRead};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+
+use super::{FrameSource, SourceCaps};
+use crate::config::Playback;
+use crate::frame::FrameBuffer;
+
+// Paces a recorded source (video file or image directory) and optionally
+// restarts it when it runs out of frames.
+pub struct ReplaySource {
+    inner: Box<dyn FrameSource>,
+    playback: Playback,
+    // Rate to pace `Playback::Native` at; None when the source paces itself
+    fps: Option<f32>,
+    looping: bool,
+    last_frame: Option<Instant>,
+}
+
+impl ReplaySource {
+    pub fn new(inner: Box<dyn FrameSource>, playback: Playback, fps: Option<f32>, looping: bool) -> Self {
+        ReplaySource {
+            inner,
+            playback,
+            fps,
+            looping,
+            last_frame: None,
+        }
+    }
+
+    fn wait(&mut self) -> Result<(), Error> {
+        match self.playback {
+            Playback::Native => {
+                if let (Some(fps), Some(last)) = (self.fps, self.last_frame) {
+                    let interval = Duration::from_secs_f32(1.0 / fps);
+                    if let Some(remaining) = interval.checked_sub(last.elapsed()) {
+                        thread::sleep(remaining);
+                    }
+                }
+            }
+            Playback::Fast => (),
+            Playback::Step => {
+                println!("Press Enter for the next frame");
+                let mut line = String::new();
+                io::stdin().lock().read_line(&mut line)?;
+            }
+        }
+        self.last_frame = Some(Instant::now());
+        Ok(())
+    }
+}
+
+impl FrameSource for ReplaySource {
+    fn open(&mut self) -> Result<SourceCaps, Error> {
+        self.last_frame = None;
+        self.inner.open()
+    }
+
+    fn next_frame(&mut self) -> Result<Option<FrameBuffer>, Error> {
+        self.wait()?;
+
+        match self.inner.next_frame()? {
+            Some(frame) => Ok(Some(frame)),
+            None if self.looping => {
+                self.inner.close()?;
+                self.inner.open()?;
+                self.inner.next_frame()
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn set_stall_timeout(&mut self, timeout: Duration) {
+        self.inner.set_stall_timeout(timeout);
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        self.inner.close()
+    }
+}